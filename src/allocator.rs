@@ -0,0 +1,254 @@
+//! Kernel heap, making `alloc` types such as `Box` and `Vec` usable.
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr;
+
+use crate::essentials::Mutex;
+
+/// Size of [`HEAP_SPACE`] in bytes.
+pub const HEAP_SIZE: usize = 1024 * 1024;
+
+/// Memory used as heap by `_start`.
+#[repr(C, align(4096))]
+pub struct HeapSpace(pub [u8; HEAP_SIZE]);
+
+/// Memory used as heap by `_start`, only to be passed to [`init`].
+pub static mut HEAP_SPACE: HeapSpace = HeapSpace([0; HEAP_SIZE]);
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+/// Gives the `heap_size` bytes at `heap_start` to the global allocator.
+///
+/// # Safety
+/// The memory must be unused, stay valid forever and be aligned to `usize`.
+/// Must be called only once.
+pub unsafe fn init(heap_start: usize, heap_size: usize) {
+    ALLOCATOR.init(heap_start, heap_size);
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
+
+/// Rounds `address` up to a multiple of `align`, which must be a power of two.
+const fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+/// Header of a free region, stored at its start.
+struct ListNode {
+    /// Size of the region including this header.
+    size: usize,
+    /// Next free region at a higher address, or null.
+    next: *mut ListNode,
+}
+
+/// First fit allocator keeping free regions in a linked list sorted by address,
+/// which lets neighbouring free regions be merged.
+pub struct LinkedListAllocator {
+    /// Empty sentinel, not part of the heap.
+    head: ListNode,
+}
+
+// The regions are owned by the allocator, not shared with anyone.
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        Self {
+            head: ListNode {
+                size: 0,
+                next: ptr::null_mut(),
+            },
+        }
+    }
+
+    /// Adds the `heap_size` bytes at `heap_start` to the free memory.
+    ///
+    /// # Safety
+    /// Same as [`init`].
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        let start = align_up(heap_start, align_of::<ListNode>());
+        let end = (heap_start + heap_size) & !(align_of::<ListNode>() - 1);
+        if end > start && end - start >= size_of::<ListNode>() {
+            self.add_free_region(start, end - start);
+        }
+    }
+
+    /// Returns size and alignment actually used for `layout`. Every block must be able
+    /// to hold a [`ListNode`] once freed.
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        (core::cmp::max(layout.size(), size_of::<ListNode>()), layout.align())
+    }
+
+    /// Inserts region into the free list, merging it with adjacent free regions.
+    unsafe fn add_free_region(&mut self, address: usize, size: usize) {
+        let head: *mut ListNode = &mut self.head;
+        let mut prev = head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < address {
+            prev = (*prev).next;
+        }
+
+        let node = address as *mut ListNode;
+        node.write(ListNode {
+            size,
+            next: (*prev).next,
+        });
+
+        let next = (*node).next;
+        if !next.is_null() && address + size == next as usize {
+            (*node).size += (*next).size;
+            (*node).next = (*next).next;
+        }
+
+        if prev != head && prev as usize + (*prev).size == address {
+            (*prev).size += (*node).size;
+            (*prev).next = (*node).next;
+        } else {
+            (*prev).next = node;
+        }
+    }
+
+    /// Returns start of a `size` bytes long block aligned to `align` inside `region`,
+    /// such that the space left before and after it can hold a [`ListNode`] or is empty.
+    fn fit(region: &ListNode, size: usize, align: usize) -> Option<usize> {
+        let region_start = region as *const ListNode as usize;
+        let region_end = region_start + region.size;
+
+        let mut start = align_up(region_start, align);
+        if start != region_start && start - region_start < size_of::<ListNode>() {
+            start = align_up(region_start + size_of::<ListNode>(), align);
+        }
+
+        let end = start.checked_add(size)?;
+        if end > region_end {
+            return None;
+        }
+        if end != region_end && region_end - end < size_of::<ListNode>() {
+            return None;
+        }
+        Some(start)
+    }
+
+    /// Allocates block for `layout`, returns null if no free region is big enough.
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        let mut prev: *mut ListNode = &mut self.head;
+        while !(*prev).next.is_null() {
+            let region = (*prev).next;
+            if let Some(start) = Self::fit(&*region, size, align) {
+                let region_start = region as usize;
+                let region_end = region_start + (*region).size;
+                (*prev).next = (*region).next;
+
+                if start != region_start {
+                    self.add_free_region(region_start, start - region_start);
+                }
+                if start + size != region_end {
+                    self.add_free_region(start + size, region_end - (start + size));
+                }
+                return start as *mut u8;
+            }
+            prev = region;
+        }
+        ptr::null_mut()
+    }
+
+    /// Frees block at `address` returned by [`allocate`](LinkedListAllocator::allocate)
+    /// for the same `layout`.
+    pub unsafe fn deallocate(&mut self, address: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(address as usize, size);
+    }
+}
+
+/// [`LinkedListAllocator`] behind a [`Mutex`], usable as the global allocator.
+pub struct LockedHeap(Mutex<LinkedListAllocator>);
+
+impl LockedHeap {
+    /// Creates heap without any memory, see [`LockedHeap::init`].
+    pub const fn empty() -> Self {
+        Self(Mutex::new(LinkedListAllocator::new()))
+    }
+
+    /// Gives memory to the heap, see [`init`].
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
+        self.0.lock_irqsafe().init(heap_start, heap_size);
+    }
+}
+
+// Interrupts are masked while the heap is locked, so handlers can allocate too.
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock_irqsafe().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock_irqsafe().deallocate(ptr, layout)
+    }
+}
+
+#[test_case]
+fn box_and_vec_allocate() {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    let boxed = Box::new(41);
+    assert_eq!(*boxed + 1, 42);
+
+    let mut vec = Vec::new();
+    for i in 0..1000 {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), 999 * 1000 / 2);
+}
+
+#[test_case]
+fn freed_memory_is_reused() {
+    use alloc::boxed::Box;
+
+    let first = Box::new([0u8; 1024]);
+    let address = &*first as *const [u8; 1024];
+    drop(first);
+    let second = Box::new([1u8; 1024]);
+    assert_eq!(&*second as *const [u8; 1024], address);
+
+    // Would run out of memory without reuse.
+    for i in 0..HEAP_SIZE {
+        let boxed = Box::new(i);
+        assert_eq!(*boxed, i);
+    }
+}
+
+#[test_case]
+fn free_regions_are_merged() {
+    #[repr(align(16))]
+    struct Space([u8; 256]);
+    let mut space = Space([0; 256]);
+    let mut heap = LinkedListAllocator::new();
+    unsafe { heap.init(space.0.as_mut_ptr() as usize, 256) };
+
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let aligned = Layout::from_size_align(32, 16).unwrap();
+    let whole = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+        let a = heap.allocate(small);
+        let b = heap.allocate(aligned);
+        let c = heap.allocate(small);
+        assert!(!a.is_null() && !b.is_null() && !c.is_null());
+        assert_eq!(b as usize % 16, 0);
+        assert!(heap.allocate(whole).is_null());
+
+        heap.deallocate(a, small);
+        heap.deallocate(c, small);
+        heap.deallocate(b, aligned);
+        assert_eq!(heap.allocate(whole), space.0.as_mut_ptr());
+    }
+}
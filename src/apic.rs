@@ -0,0 +1,174 @@
+//! Local advanced programmable interrupt controller (APIC) and its timer.
+//!
+//! The legacy PIC keeps delivering device IRQs, the local APIC is only used
+//! for its timer, which runs with the much higher bus frequency than the PIT.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cpu::{self, IA32_APIC_BASE};
+use crate::frame_allocator::{PhysFrame, FRAME_ALLOCATOR};
+use crate::interrupts::{self, InterruptStackFrame};
+use crate::mmio::Mmio;
+use crate::paging::{self, MapError, Page, PageTableFlags};
+
+/// Vector the APIC timer interrupts on, the first one after the PIC's.
+pub const TIMER_VECTOR: u8 = 0x30;
+/// Vector of spurious APIC interrupts. Older CPUs require the low four bits set.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Bits of [`IA32_APIC_BASE`] holding the physical address of the registers.
+const BASE_ADDRESS_MASK: u64 = 0xffff_f000;
+/// [`IA32_APIC_BASE`] bit enabling the APIC.
+const GLOBAL_ENABLE: u64 = 1 << 11;
+
+/// End of interrupt register.
+const EOI: usize = 0xb0;
+/// Spurious interrupt vector register.
+const SPURIOUS: usize = 0xf0;
+/// [`SPURIOUS`] bit enabling the APIC in software.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// Address of the register page, identity mapped by [`init`], `0` before.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns register at `offset` of the register page.
+fn register(offset: usize) -> Mmio<u32> {
+    let base = BASE.load(Ordering::Relaxed);
+    assert_ne!(base, 0, "local APIC not initialized");
+    // `init` mapped the whole register page.
+    unsafe { Mmio::new((base + offset) as *mut u32) }
+}
+
+/// Maps the register page uncached and enables the local APIC.
+/// Does nothing if it is enabled already.
+///
+/// # Panics
+/// If the CPU has no local APIC or no MSRs.
+pub fn init() {
+    if BASE.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    assert!(cpu::has_apic() && cpu::has_msr(), "no local APIC");
+
+    let apic_base = unsafe { cpu::read_msr(IA32_APIC_BASE) };
+    let address = (apic_base & BASE_ADDRESS_MASK) as usize;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    // Paging is enabled and the register page lies above all memory.
+    let mapped = unsafe {
+        paging::active_mapper().map_to(Page::containing_address(address),
+                                       PhysFrame::containing_address(address as u64),
+                                       flags, allocator.as_mut().unwrap())
+    };
+    match mapped {
+        Ok(()) | Err(MapError::AlreadyMapped(_)) => {},
+        Err(error) => panic!("mapping the local APIC failed: {:?}", error),
+    }
+
+    unsafe { cpu::write_msr(IA32_APIC_BASE, apic_base | GLOBAL_ENABLE) };
+    BASE.store(address, Ordering::Relaxed);
+    register(SPURIOUS).write(SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Signals the end of the current APIC interrupt.
+pub fn send_eoi() {
+    register(EOI).write(0);
+}
+
+pub(crate) extern "C" fn apic_spurious_interrupt_handler(_frame: &InterruptStackFrame) {
+    interrupts::count(SPURIOUS_VECTOR);
+    // Spurious interrupts aren't in service, so they take no EOI.
+}
+
+/// Timer of the local APIC, counting down with the bus frequency divided by 16.
+pub mod timer {
+    use super::*;
+    use crate::{pic, pit};
+
+    /// Local vector table entry of the timer.
+    const LVT_TIMER: usize = 0x320;
+    const INITIAL_COUNT: usize = 0x380;
+    const CURRENT_COUNT: usize = 0x390;
+    const DIVIDE_CONFIGURATION: usize = 0x3e0;
+
+    /// [`DIVIDE_CONFIGURATION`] value dividing the bus frequency by 16.
+    const DIVIDE_BY_16: u32 = 0b0011;
+    /// [`LVT_TIMER`] bit suppressing the interrupt.
+    const MASKED: u32 = 1 << 16;
+    /// [`LVT_TIMER`] bit reloading the initial count when it reaches zero.
+    const PERIODIC: u32 = 1 << 17;
+
+    /// Returns how much the masked timer counts down while `f` runs.
+    pub fn count_during(f: impl FnOnce()) -> u32 {
+        register(DIVIDE_CONFIGURATION).write(DIVIDE_BY_16);
+        register(LVT_TIMER).write(MASKED | TIMER_VECTOR as u32);
+        register(INITIAL_COUNT).write(u32::MAX);
+        f();
+        let remaining = register(CURRENT_COUNT).read();
+        register(INITIAL_COUNT).write(0);
+        u32::MAX - remaining
+    }
+
+    /// Returns how much the timer counts down per second, measured with
+    /// about 50 ms of PIT ticks. Interrupts must be enabled.
+    pub fn calibrate() -> u32 {
+        let pit_frequency = pit::frequency();
+        let ticks = core::cmp::max(pit_frequency / 20, 1) as u64;
+
+        // Start right at a tick, not somewhere in between.
+        pit::sleep_ticks(1);
+        let counted = count_during(|| pit::sleep_ticks(ticks));
+        (counted as u64 * pit_frequency as u64 / ticks) as u32
+    }
+
+    /// Enables the local APIC and makes its timer interrupt `hz` times per
+    /// second on [`TIMER_VECTOR`]. From then on it advances [`pit::ticks`]
+    /// instead of the PIT, whose IRQ gets masked.
+    ///
+    /// The PIT must be running and interrupts enabled for the calibration.
+    pub fn init(hz: u32) {
+        super::init();
+        let counts_per_second = calibrate();
+
+        register(DIVIDE_CONFIGURATION).write(DIVIDE_BY_16);
+        register(LVT_TIMER).write(PERIODIC | TIMER_VECTOR as u32);
+        register(INITIAL_COUNT).write(core::cmp::max(counts_per_second / core::cmp::max(hz, 1), 1));
+        pic::set_mask(pit::TIMER_IRQ);
+    }
+
+    /// Stops the timer and lets the PIT advance [`pit::ticks`] again.
+    #[allow(dead_code)]
+    pub fn stop() {
+        register(LVT_TIMER).write(MASKED | TIMER_VECTOR as u32);
+        register(INITIAL_COUNT).write(0);
+        pic::clear_mask(pit::TIMER_IRQ);
+    }
+
+    pub(crate) extern "C" fn apic_timer_interrupt_handler(_frame: &InterruptStackFrame) {
+        interrupts::count(TIMER_VECTOR);
+        pit::tick();
+        send_eoi();
+    }
+}
+
+#[test_case]
+fn timer_agrees_with_pit() {
+    use crate::pit;
+
+    init();
+    pit::set_frequency(1000);
+    let counts_per_second = timer::calibrate() as u64;
+    assert!(counts_per_second > 0);
+    let counted = timer::count_during(|| pit::sleep_ticks(50)) as u64;
+    let milliseconds = counted * 1000 / counts_per_second;
+    assert!((45..=55).contains(&milliseconds), "50 PIT ticks took {} APIC milliseconds", milliseconds);
+
+    // Periodic interrupts advance the ticks in place of the PIT.
+    let interrupts_before = interrupts::STATS[TIMER_VECTOR as usize].load(Ordering::Relaxed);
+    timer::init(1000);
+    let start = pit::ticks();
+    pit::sleep_ticks(10);
+    assert!(pit::ticks() >= start + 10);
+    timer::stop();
+    assert!(interrupts::STATS[TIMER_VECTOR as usize].load(Ordering::Relaxed) - interrupts_before >= 10);
+    pit::set_frequency(pit::DEFAULT_FREQUENCY);
+}
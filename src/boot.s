@@ -34,17 +34,21 @@ multiboot:
 [EXTERN _start]                 ; This is the entry point of our C code
 
 start:
-  push    ebx                   ; Load multiboot header location
-
   ; Execute the kernel:
   cli                           ; Disable interrupts.
   mov esp, init_stack           ; Enable stack
+  push    ebx                   ; Pass multiboot information structure location
+  push    eax                   ; Pass multiboot magic value
   call _start                   ; call our main() function.
   jmp $                         ; Enter an infinite loop, to stop the processor
                                 ; executing whatever rubbish is in the memory
                                 ; after our kernel! 
 
-section .bss
+[GLOBAL stack_bottom]          ; Lowest address of the kernel stack.
+
+section .bss align=0x1000
+  resb 0x1000                   ; Guard page, left unmapped once paging is enabled
+stack_bottom:
   resb 0x1000*2
 init_stack:
 
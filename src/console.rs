@@ -0,0 +1,130 @@
+//! Output devices written to by `print!` and `println!`, selected at runtime.
+use core::fmt;
+
+use crate::essentials::Mutex;
+use lazy_static::lazy_static;
+
+/// Output device characters can be written to.
+pub trait Console: Send {
+    fn write_byte(&mut self, byte: u8);
+
+    fn write_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+    }
+}
+
+/// Most consoles a [`MultiConsole`] writes to.
+pub const MAX_CONSOLES: usize = 4;
+
+/// Console writing everything to several consoles, each behind its own lock.
+#[derive(Clone, Copy)]
+pub struct MultiConsole {
+    consoles: [Option<&'static Mutex<dyn Console>>; MAX_CONSOLES],
+}
+
+impl MultiConsole {
+    /// Creates console without any backends, which discards writes.
+    pub const fn new() -> Self {
+        Self { consoles: [None; MAX_CONSOLES] }
+    }
+
+    /// Returns console additionally writing to `console`.
+    ///
+    /// # Panics
+    /// If [`MAX_CONSOLES`] backends were already added.
+    pub fn with(mut self, console: &'static Mutex<dyn Console>) -> Self {
+        let free = self.consoles.iter_mut().find(|slot| slot.is_none()).expect("too many consoles");
+        *free = Some(console);
+        self
+    }
+
+    /// Writes `args` to every backend, locking one at a time with interrupts masked.
+    pub fn write_fmt(&self, args: fmt::Arguments) {
+        for console in self.consoles.iter().flatten() {
+            let mut console = console.lock_irqsafe();
+            // `Console` writes can't fail.
+            fmt::write(&mut Writer(&mut *console), args).unwrap();
+        }
+    }
+}
+
+impl Console for MultiConsole {
+    fn write_byte(&mut self, byte: u8) {
+        for console in self.consoles.iter().flatten() {
+            console.lock_irqsafe().write_byte(byte);
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        for console in self.consoles.iter().flatten() {
+            console.lock_irqsafe().write_str(s);
+        }
+    }
+}
+
+/// Adapts a [`Console`] to `fmt::Write`.
+struct Writer<'a>(&'a mut dyn Console);
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+lazy_static! {
+    /// Consoles `print!` writes to, only the VGA display by default.
+    static ref CONSOLE: Mutex<MultiConsole> =
+        Mutex::new(MultiConsole::new().with(&*crate::monitor::VGA::BUFFER));
+}
+
+/// Makes `print!` and `println!` write to `console`.
+pub fn set_console(console: MultiConsole) {
+    *CONSOLE.lock_irqsafe() = console;
+}
+
+/// Returns consoles `print!` and `println!` write to.
+pub fn console() -> MultiConsole {
+    *CONSOLE.lock_irqsafe()
+}
+
+/// Writes `args` to the active consoles.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    // Not holding `CONSOLE` while writing lets a backend print itself.
+    console().write_fmt(args);
+}
+
+#[cfg(test)]
+struct Recorder {
+    bytes: [u8; 32],
+    len: usize,
+}
+
+#[cfg(test)]
+impl Console for Recorder {
+    fn write_byte(&mut self, byte: u8) {
+        self.bytes[self.len] = byte;
+        self.len += 1;
+    }
+}
+
+#[cfg(test)]
+static FIRST: Mutex<Recorder> = Mutex::new(Recorder { bytes: [0; 32], len: 0 });
+#[cfg(test)]
+static SECOND: Mutex<Recorder> = Mutex::new(Recorder { bytes: [0; 32], len: 0 });
+
+#[test_case]
+fn println_reaches_every_console() {
+    let saved = console();
+    set_console(MultiConsole::new().with(&FIRST).with(&SECOND));
+    crate::println!("mock {}", 42);
+    set_console(saved);
+
+    for recorder in [&FIRST, &SECOND] {
+        let recorder = recorder.lock();
+        assert_eq!(&recorder.bytes[..recorder.len], b"mock 42\n");
+    }
+}
@@ -0,0 +1,232 @@
+//! CPU identification, feature detection, model-specific and segment registers.
+use core::arch::asm;
+
+use bit_field::BitField;
+
+/// Registers returned by [`cpuid`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Model-specific register holding the local APIC base address.
+/// Bit 11 is the APIC global enable flag.
+pub const IA32_APIC_BASE: u32 = 0x1b;
+
+/// Leaf returning the highest basic leaf and the vendor string.
+const VENDOR_LEAF: u32 = 0;
+/// Leaf returning version and feature flags.
+const FEATURES_LEAF: u32 = 1;
+
+/// Executes `cpuid` for `leaf` with subleaf 0.
+///
+/// All CPUs the kernel runs on (i586 and later) support `cpuid`.
+#[inline]
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    let (eax, ebx, ecx, edx);
+    // `ebx` can't be an operand, so it is saved in another register.
+    unsafe {
+    asm!(
+        "mov {tmp}, ebx",
+        "cpuid",
+        "xchg {tmp}, ebx",
+        tmp = out(reg) ebx,
+        inout("eax") leaf => eax,
+        inout("ecx") 0 => ecx,
+        out("edx") edx,
+        options(nomem, nostack, preserves_flags),
+    );
+    }
+    CpuidResult { eax, ebx, ecx, edx }
+}
+
+/// Returns the 12 byte vendor string, such as `b"GenuineIntel"`.
+#[allow(dead_code)]
+pub fn vendor_string() -> [u8; 12] {
+    let result = cpuid(VENDOR_LEAF);
+    let mut vendor = [0; 12];
+    vendor[0..4].copy_from_slice(&result.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&result.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&result.ecx.to_le_bytes());
+    vendor
+}
+
+/// Returns whether the CPU has a local APIC.
+pub fn has_apic() -> bool {
+    cpuid(FEATURES_LEAF).edx.get_bit(9)
+}
+
+/// Returns whether the CPU supports model-specific registers (`rdmsr`/`wrmsr`).
+pub fn has_msr() -> bool {
+    cpuid(FEATURES_LEAF).edx.get_bit(5)
+}
+
+/// Reads model-specific register `msr` (`EDX:EAX` combined into one value).
+///
+/// # Safety
+///
+/// The CPU must support MSRs, see [`has_msr`]. Reading a MSR the CPU
+/// doesn't implement raises a general protection fault.
+#[inline]
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    asm!(
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags),
+    );
+    ( (high as u64) << 32) | (low as u64)
+}
+
+/// Writes `value` to model-specific register `msr`.
+///
+/// # Safety
+///
+/// Same as [`read_msr`]. Writing a present MSR can also change
+/// fundamental behavior of the CPU.
+#[inline]
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags),
+    );
+}
+
+/// Returns the code segment selector (`cs`).
+#[inline]
+pub fn read_cs() -> u16 {
+    let value: u16;
+    unsafe {
+    asm!("mov {:x}, cs", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Returns the data segment selector (`ds`).
+#[allow(dead_code)]
+#[inline]
+pub fn read_ds() -> u16 {
+    let value: u16;
+    unsafe {
+    asm!("mov {:x}, ds", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Returns the stack segment selector (`ss`).
+#[allow(dead_code)]
+#[inline]
+pub fn read_ss() -> u16 {
+    let value: u16;
+    unsafe {
+    asm!("mov {:x}, ss", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Returns the extra segment selector (`es`).
+#[allow(dead_code)]
+#[inline]
+pub fn read_es() -> u16 {
+    let value: u16;
+    unsafe {
+    asm!("mov {:x}, es", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Calls `emit` with the return address of every caller up the frame pointer
+/// chain, starting with the caller of `backtrace`, up to `max_frames` of them.
+/// Implausible frame pointers end the walk, see [`crate::panic::walk_frames`].
+///
+/// Needs code built with frame pointers, which `.cargo/config.toml` forces
+/// through `-C force-frame-pointers=yes`. Otherwise the walk ends early or
+/// emits addresses that aren't return addresses.
+#[allow(dead_code)]
+#[inline(never)]
+pub fn backtrace(max_frames: usize, emit: impl FnMut(usize)) {
+    let ebp: usize;
+    unsafe {
+    asm!("mov {}, ebp", out(reg) ebp, options(nomem, nostack, preserves_flags));
+    // Frames of the running code lie on a valid stack.
+    crate::panic::walk_frames(ebp, max_frames, emit);
+    }
+}
+
+#[test_case]
+fn apic_base_enabled() {
+    assert!(has_msr() && has_apic());
+
+    let apic_base = unsafe { read_msr(IA32_APIC_BASE) };
+    assert!(apic_base.get_bit(11));
+    // The default base address, page aligned.
+    assert_eq!(apic_base & 0xffff_f000, 0xfee0_0000);
+
+    // Writing back the same value changes nothing.
+    unsafe { write_msr(IA32_APIC_BASE, apic_base) };
+    assert_eq!(unsafe { read_msr(IA32_APIC_BASE) }, apic_base);
+}
+
+#[test_case]
+fn vendor_is_known() {
+    let vendor = vendor_string();
+    assert!([b"GenuineIntel", b"AuthenticAMD", b"TCGTCGTCGTCG"].contains(&&vendor),
+            "unknown vendor {:?}", core::str::from_utf8(&vendor));
+    assert!(cpuid(VENDOR_LEAF).eax >= FEATURES_LEAF);
+    assert!(has_msr());
+}
+
+#[test_case]
+fn segment_registers_are_stable() {
+    let cs = read_cs();
+    assert_ne!(cs, 0);
+    for _ in 0..3 {
+        assert_eq!(read_cs(), cs);
+    }
+    // Flat segments, so data and stack share one selector.
+    assert_eq!(read_ds(), read_ss());
+    assert_eq!(read_es(), read_ds());
+}
+
+#[test_case]
+fn backtrace_returns_to_callers() {
+    use core::hint::black_box;
+
+    #[inline(never)]
+    fn first(addresses: &mut [usize; 3]) {
+        second(addresses);
+        // Keeps the calls from becoming jumps, which would skip frames.
+        black_box(());
+    }
+
+    #[inline(never)]
+    fn second(addresses: &mut [usize; 3]) {
+        third(addresses);
+        black_box(());
+    }
+
+    #[inline(never)]
+    fn third(addresses: &mut [usize; 3]) {
+        let mut count = 0;
+        backtrace(3, |address| {
+            addresses[count] = address;
+            count += 1;
+        });
+        assert_eq!(count, 3);
+    }
+
+    let mut addresses = [0; 3];
+    first(&mut addresses);
+    let [a, b, c] = addresses;
+    assert!(a != 0 && b != 0 && c != 0);
+    assert!(a != b && b != c && a != c);
+}
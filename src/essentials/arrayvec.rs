@@ -0,0 +1,146 @@
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+/// List of up to `N` elements stored inline, usable before the heap is.
+pub struct ArrayVec<T, const N: usize> {
+    /// The first `len` elements are initialized.
+    elements: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    #[allow(dead_code)]
+    pub const fn new() -> Self {
+        Self {
+            // An array of `MaybeUninit` needs no initialization.
+            elements: unsafe { MaybeUninit::<[MaybeUninit<T>; N]>::uninit().assume_init() },
+            len: 0,
+        }
+    }
+
+    /// Appends `value`, or returns it back if the list is full.
+    #[allow(dead_code)]
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(value);
+        }
+        self.elements[self.len].write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes and returns the last element.
+    #[allow(dead_code)]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // The element was initialized and is no longer counted by `len`.
+        Some(unsafe { self.elements[self.len].assume_init_read() })
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[allow(dead_code)]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Removes all elements, dropping them.
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        let len = core::mem::replace(&mut self.len, 0);
+        // A panicking drop leaks the rest instead of dropping them twice.
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.elements.as_mut_ptr() as *mut T, len)) };
+    }
+
+    #[allow(dead_code)]
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.elements.as_ptr() as *const T, self.len) }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.elements.as_mut_ptr() as *mut T, self.len) }
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Indexing and iteration go through the slice of the elements.
+impl<T, const N: usize> Deref for ArrayVec<T, N> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: core::fmt::Debug, const N: usize> core::fmt::Debug for ArrayVec<T, N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[test_case]
+fn push_until_full_and_pop() {
+    let mut list = ArrayVec::<u32, 3>::new();
+    assert!(list.is_empty());
+    assert_eq!(list.pop(), None);
+    for value in 1..=3 {
+        assert_eq!(list.push(value), Ok(()));
+    }
+    assert!(list.is_full());
+    assert_eq!(list.push(4), Err(4));
+    assert_eq!(list.as_slice(), &[1, 2, 3]);
+
+    list[0] = 10;
+    assert_eq!((list[0], list.len()), (10, 3));
+    assert_eq!(list.pop(), Some(3));
+    assert_eq!(list.pop(), Some(2));
+    assert_eq!(list.push(5), Ok(()));
+    assert_eq!(list.iter().sum::<u32>(), 15);
+}
+
+#[test_case]
+fn only_live_elements_are_dropped() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Counted;
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    {
+        let mut list = ArrayVec::<Counted, 4>::new();
+        for _ in 0..3 {
+            assert!(list.push(Counted).is_ok());
+        }
+        drop(list.pop());
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    }
+    // Two left in the list, the uninitialized slot isn't dropped.
+    assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+}
@@ -1,7 +1,26 @@
 //! Implementation of important structures, traits and functions
 //! used everywhere in the kernel.
 pub mod singleton;
+#[allow(unused_imports)]
 pub use singleton::*;
 
 pub mod mutex;
 pub use mutex::*;
+
+pub mod rwlock;
+#[allow(unused_imports)]
+pub use rwlock::*;
+
+pub mod once;
+pub use once::*;
+
+pub mod semaphore;
+#[allow(unused_imports)]
+pub use semaphore::*;
+
+pub mod volatile;
+pub use volatile::*;
+
+pub mod arrayvec;
+#[allow(unused_imports)]
+pub use arrayvec::*;
@@ -1,17 +1,77 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 use core::ops::{Deref, DerefMut};
 use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
+#[cfg(debug_assertions)]
+use core::panic::Location;
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicPtr;
 
+use crate::interrupts;
+
+/// Spins of [`Mutex::lock`] on a held lock after which debug builds report
+/// a possible deadlock, roughly a second.
+#[cfg(debug_assertions)]
+const DEADLOCK_SPINS: usize = 50_000_000;
+
+/// Spin lock protecting a `T`.
+///
+/// A guard held when a panic begins and dropped while it is still running
+/// poisons the mutex, which [`Mutex::lock_checked`] reports, as the inner value
+/// may have been left inconsistent. The kernel aborts instead of unwinding,
+/// so only the panic handler can release such a guard, and [`Mutex::lock`]
+/// ignores poisoning.
+///
+/// In debug builds the mutex remembers where it was locked, and a
+/// [`lock`](Mutex::lock) spinning for [`DEADLOCK_SPINS`] iterations prints
+/// `possible deadlock, held by <location>` to COM1, once per mutex.
 pub struct Mutex<T: ?Sized> {
     lock: AtomicBool,
+    poisoned: AtomicBool,
+    /// Caller of the last successful lock, null if not locked through a method.
+    #[cfg(debug_assertions)]
+    holder: AtomicPtr<Location<'static>>,
+    /// Whether a possible deadlock was reported already.
+    #[cfg(debug_assertions)]
+    reported: AtomicBool,
     inner: UnsafeCell<T>,
 }
 
 pub struct MutexGuard<'a, T: ?Sized> {
     lock: &'a AtomicBool,
+    poisoned: &'a AtomicBool,
+    /// Whether a panic was running when the lock was taken.
+    panicking: bool,
     inner: &'a mut T
 }
 
+/// Error of [`Mutex::lock_checked`] for a poisoned mutex, holding the guard anyway.
+#[allow(dead_code)]
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+impl<G> PoisonError<G> {
+    /// Returns the guard, accepting the inner value as it is.
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+}
+
+impl<G> core::fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("PoisonError")
+    }
+}
+
+/// Guard returned by [`Mutex::lock_irqsafe`]. Releases the lock and
+/// then restores the interrupt flag when dropped.
+pub struct IrqSafeMutexGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    were_enabled: bool,
+}
+
 // Same unsafe impls as `std::sync::Mutex`
 unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
 unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
@@ -21,18 +81,165 @@ impl<T> Mutex<T> {
         Self {
             inner: UnsafeCell::new(inner),
             lock: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            holder: AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(debug_assertions)]
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Creates mutex that is already locked, for example while `inner` is still
+    /// being initialized. Nobody can lock it until [`unlock`](Self::unlock) is called.
+    #[allow(dead_code)]
+    pub const fn new_locked(inner: T) -> Self {
+        Self {
+            inner: UnsafeCell::new(inner),
+            lock: AtomicBool::new(true),
+            poisoned: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            holder: AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(debug_assertions)]
+            reported: AtomicBool::new(false),
         }
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
-        while self.lock.swap(true, Ordering::Acquire) {}
+    /// Consumes the mutex and returns the inner value,
+    /// owning the mutex already guarantees exclusive access.
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(debug_assertions)]
+        let mut spins = 0usize;
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            // Wait with plain loads, which don't take the cache line exclusively
+            // like `swap` does, and retry once the lock looks free.
+            while self.lock.load(Ordering::Relaxed) {
+                #[cfg(debug_assertions)]
+                {
+                    spins += 1;
+                    if spins == DEADLOCK_SPINS {
+                        self.report_deadlock();
+                    }
+                }
+                core::hint::spin_loop();
+            }
+        }
+    }
 
-        MutexGuard {
+    /// Prints the holder of the lock to COM1, unless a deadlock of this mutex
+    /// was reported already.
+    #[cfg(debug_assertions)]
+    #[cold]
+    fn report_deadlock(&self) {
+        use core::fmt::Write;
+
+        if self.reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        // `SERIAL1` itself may be the deadlocked mutex, so the port is written
+        // without it. At worst the report interleaves with the holder's output.
+        let mut serial = unsafe { crate::serial::SerialPort::new(crate::serial::COM1) };
+        let _ = match self.holder() {
+            Some(location) => writeln!(serial, "possible deadlock, held by {}", location),
+            None => writeln!(serial, "possible deadlock, held by unknown"),
+        };
+    }
+
+    /// Returns where the mutex was last locked, `None` if it was created locked.
+    #[cfg(debug_assertions)]
+    fn holder(&self) -> Option<&'static Location<'static>> {
+        unsafe { self.holder.load(Ordering::Relaxed).as_ref() }
+    }
+
+    /// Locks the mutex, runs `f` on the inner value and unlocks it again.
+    /// The critical section is exactly the call of `f`.
+    #[allow(dead_code)]
+    #[track_caller]
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Locks the mutex if it isn't locked already, otherwise returns `None`
+    /// without waiting.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.lock.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        #[cfg(debug_assertions)]
+        self.holder.store(Location::caller() as *const _ as *mut _, Ordering::Relaxed);
+
+        Some(MutexGuard {
             lock: &self.lock,
+            poisoned: &self.poisoned,
+            panicking: crate::panic::is_panicking(),
             inner: unsafe { &mut *self.inner.get() },
+        })
+    }
+
+    /// Locks the mutex like [`lock`](Self::lock), but fails if it is poisoned.
+    #[allow(dead_code)]
+    pub fn lock_checked(&self) -> Result<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>> {
+        let guard = self.lock();
+        if self.is_poisoned() {
+            Err(PoisonError { guard })
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Returns whether a guard was released by a panic, see [`Mutex`].
+    #[allow(dead_code)]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Marks the inner value as consistent again.
+    #[allow(dead_code)]
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Disables interrupts and then locks the mutex.
+    ///
+    /// Use this for data shared with interrupt handlers: a handler can't
+    /// preempt the holder on the same CPU and spin forever on the held lock.
+    /// Interrupts are restored to their previous state when the guard is dropped.
+    #[track_caller]
+    pub fn lock_irqsafe(&self) -> IrqSafeMutexGuard<'_, T> {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        IrqSafeMutexGuard {
+            guard: ManuallyDrop::new(self.lock()),
+            were_enabled,
         }
     }
 
+    /// Returns whether the mutex is locked. Only a snapshot, useful for debugging.
+    #[allow(dead_code)]
+    pub fn is_locked(&self) -> bool {
+        self.lock.load(Ordering::Relaxed)
+    }
+
+    /// Returns mutable reference to the inner value without locking,
+    /// `&mut self` already guarantees exclusive access.
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    #[allow(dead_code)]
     pub fn unlock(&self) {
         self.lock.store(false, Ordering::Release);
     }
@@ -40,6 +247,9 @@ impl<T> Mutex<T> {
 
 impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
+        if !self.panicking && crate::panic::is_panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
         self.lock.store(false, Ordering::Release);
     }
 }
@@ -56,3 +266,144 @@ impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
         self.inner
     }
 }
+
+impl<'a, T: ?Sized> Drop for IrqSafeMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        // The lock must be released before interrupts come back.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.were_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for IrqSafeMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for IrqSafeMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[test_case]
+fn lock_irqsafe_masks_interrupts() {
+    let mutex = Mutex::new(0);
+    let were_enabled = interrupts::are_enabled();
+    {
+        let mut guard = mutex.lock_irqsafe();
+        *guard += 1;
+        // No interrupt handler can run now and spin on the held lock.
+        assert!(!interrupts::are_enabled());
+        assert!(mutex.lock.load(Ordering::Relaxed));
+    }
+    assert!(!mutex.lock.load(Ordering::Relaxed));
+    assert_eq!(interrupts::are_enabled(), were_enabled);
+    assert_eq!(*mutex.lock(), 1);
+}
+
+#[test_case]
+fn try_lock_fails_while_locked() {
+    let mutex = Mutex::new(0);
+    {
+        let _guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_none());
+    }
+    assert!(mutex.try_lock().is_some());
+}
+
+#[test_case]
+fn is_locked_reflects_guard() {
+    let mutex = Mutex::new(0);
+    assert!(!mutex.is_locked());
+    {
+        let _guard = mutex.lock();
+        assert!(mutex.is_locked());
+    }
+    assert!(!mutex.is_locked());
+}
+
+#[test_case]
+fn into_inner_returns_value() {
+    let mutex = Mutex::new(1);
+    *mutex.lock() += 1;
+    assert_eq!(mutex.into_inner(), 2);
+}
+
+#[test_case]
+fn new_locked_is_locked_until_unlocked() {
+    let mutex = Mutex::new_locked(0);
+    assert!(mutex.is_locked());
+    assert!(mutex.try_lock().is_none());
+    mutex.unlock();
+    assert!(!mutex.is_locked());
+    assert_eq!(*mutex.try_lock().unwrap(), 0);
+}
+
+#[cfg(debug_assertions)]
+#[test_case]
+fn holder_is_recorded() {
+    let mutex = Mutex::new_locked(0);
+    assert!(mutex.holder().is_none());
+    mutex.unlock();
+
+    let (guard, line) = (mutex.lock(), line!());
+    assert_eq!((mutex.holder().unwrap().file(), mutex.holder().unwrap().line()), (file!(), line));
+    drop(guard);
+    let (_guard, line) = (mutex.lock_irqsafe(), line!());
+    assert_eq!(mutex.holder().unwrap().line(), line);
+}
+
+#[test_case]
+fn get_mut_mutates_inner() {
+    let mut mutex = Mutex::new(0);
+    *mutex.get_mut() = 5;
+    assert_eq!(*mutex.lock(), 5);
+}
+
+#[test_case]
+fn with_runs_closure_under_lock() {
+    let mutex = Mutex::new(0);
+    for _ in 0..10 {
+        mutex.with(|counter| {
+            assert!(mutex.is_locked());
+            *counter += 1;
+        });
+    }
+    assert!(!mutex.is_locked());
+    assert_eq!(mutex.with(|counter| *counter), 10);
+}
+
+#[test_case]
+fn panic_poisons_held_lock() {
+    let mutex = Mutex::new(0);
+    interrupts::without_interrupts(|| {
+        let mut guard = mutex.lock();
+        *guard = 1;
+        // As if the panic handler released the guard.
+        crate::panic::set_panicking(true);
+        drop(guard);
+        // Locks taken during the panic don't poison.
+        drop(mutex.lock());
+        crate::panic::set_panicking(false);
+    });
+
+    assert!(mutex.is_poisoned());
+    let guard = mutex.lock_checked().err().unwrap().into_inner();
+    assert_eq!(*guard, 1);
+    drop(guard);
+    mutex.clear_poison();
+    assert!(mutex.lock_checked().is_ok());
+
+    let clean = Mutex::new(0);
+    interrupts::without_interrupts(|| {
+        crate::panic::set_panicking(true);
+        drop(clean.lock());
+        crate::panic::set_panicking(false);
+    });
+    assert!(!clean.is_poisoned());
+}
@@ -0,0 +1,95 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+
+/// Value initialized exactly once, on first use, a lightweight `lazy_static!`.
+///
+/// Usage:
+/// ```
+/// static GDT: Once<Gdt> = Once::new();
+/// let gdt = GDT.call_once(Gdt::new);
+/// ```
+pub struct Once<T> {
+    /// One of [`INCOMPLETE`], [`RUNNING`] and [`COMPLETE`].
+    state: AtomicUsize,
+    inner: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Same unsafe impls as `std::sync::OnceLock`
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(INCOMPLETE),
+            inner: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value, initializing it with `f` if this is the first call.
+    ///
+    /// Concurrent callers wait until the first one's `f` returns.
+    /// If `f` panics or calls `call_once` on the same `Once`, callers wait forever.
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        if self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+            unsafe { (*self.inner.get()).write(f()) };
+            self.state.store(COMPLETE, Ordering::Release);
+        }
+
+        loop {
+            if let Some(inner) = self.get() {
+                return inner;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the value if it is initialized already.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            Some(unsafe { (*self.inner.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.inner.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+#[test_case]
+fn call_once_initializes_once() {
+    let once = Once::new();
+    let mut calls = 0;
+    assert!(once.get().is_none());
+
+    let first = once.call_once(|| {
+        // Not visible before the initializer returns.
+        assert!(once.get().is_none());
+        // There is no second CPU to race with, and a second `call_once` from
+        // here would wait forever. So check the state a racing caller finds:
+        // it can't claim the initialization and waits for `COMPLETE` instead.
+        assert_eq!(once.state.load(Ordering::Relaxed), RUNNING);
+        assert!(once.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire).is_err());
+        calls += 1;
+        5
+    }) as *const i32;
+    let second = once.call_once(|| {
+        calls += 1;
+        6
+    }) as *const i32;
+
+    assert_eq!(calls, 1);
+    assert_eq!(first, second);
+    assert_eq!(once.get(), Some(&5));
+}
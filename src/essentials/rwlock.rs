@@ -0,0 +1,162 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::ops::{Deref, DerefMut};
+use core::cell::UnsafeCell;
+
+/// Bit of [`RwLock::state`] set while a writer holds the lock.
+const WRITER: usize = 1;
+/// Amount added to [`RwLock::state`] by every reader.
+const READER: usize = 2;
+
+/// Spinning lock that allows either many readers or one writer at a time.
+///
+/// Writers wait for all readers to leave, so a steady stream of readers can starve them.
+pub struct RwLock<T: ?Sized> {
+    /// [`WRITER`] bit and number of readers times [`READER`].
+    state: AtomicUsize,
+    inner: UnsafeCell<T>,
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    state: &'a AtomicUsize,
+    inner: &'a T,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    state: &'a AtomicUsize,
+    inner: &'a mut T,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    #[allow(dead_code)]
+    pub const fn new(inner: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            inner: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Locks for shared reading, waiting while a writer holds the lock.
+    #[allow(dead_code)]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) & WRITER != 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Locks for shared reading, or returns `None` if a writer holds the lock.
+    #[allow(dead_code)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & WRITER != 0 {
+                return None;
+            }
+            // Fails only when another reader came or left in the meantime.
+            match self.state.compare_exchange_weak(state, state + READER, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+
+        Some(RwLockReadGuard {
+            state: &self.state,
+            inner: unsafe { &*self.inner.get() },
+        })
+    }
+
+    /// Locks for exclusive writing, waiting until all readers and writers left.
+    #[allow(dead_code)]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) != 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Locks for exclusive writing, or returns `None` if the lock is held.
+    #[allow(dead_code)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state.compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed).ok()?;
+
+        Some(RwLockWriteGuard {
+            state: &self.state,
+            inner: unsafe { &mut *self.inner.get() },
+        })
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.inner
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.state.store(0, Ordering::Release);
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.inner
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner
+    }
+}
+
+#[test_case]
+fn readers_are_counted() {
+    let lock = RwLock::new(0);
+    let first = lock.read();
+    let second = lock.try_read().unwrap();
+    assert_eq!(lock.state.load(Ordering::Relaxed), 2 * READER);
+    assert_eq!(*first + *second, 0);
+
+    drop(first);
+    assert_eq!(lock.state.load(Ordering::Relaxed), READER);
+    drop(second);
+    assert_eq!(lock.state.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn writer_is_exclusive() {
+    let lock = RwLock::new(0);
+    {
+        let _reader = lock.read();
+        assert!(lock.try_write().is_none());
+    }
+    {
+        let mut writer = lock.write();
+        *writer = 1;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+    }
+    assert_eq!(*lock.read(), 1);
+    assert!(lock.try_write().is_some());
+}
@@ -0,0 +1,79 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Spinning counting semaphore, letting up to a number of holders in at a time.
+pub struct Semaphore {
+    /// Number of permits left.
+    count: AtomicUsize,
+    /// Most permits [`Semaphore::release`] hands back.
+    max: usize,
+}
+
+impl Semaphore {
+    /// Creates semaphore with `count` permits and no limit on releases.
+    #[allow(dead_code)]
+    pub const fn new(count: usize) -> Self {
+        Self::with_max(count, usize::MAX)
+    }
+
+    /// Creates semaphore with `count` permits that never holds more than `max`.
+    #[allow(dead_code)]
+    pub const fn with_max(count: usize, max: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(if count < max { count } else { max }),
+            max,
+        }
+    }
+
+    /// Takes a permit, waiting while there are none left.
+    #[allow(dead_code)]
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            while self.count.load(Ordering::Relaxed) == 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Takes a permit, or returns `false` if there are none left.
+    #[allow(dead_code)]
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| count.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Returns a permit. Releases beyond the maximum are ignored.
+    #[allow(dead_code)]
+    pub fn release(&self) {
+        let _ = self.count.fetch_update(Ordering::Release, Ordering::Relaxed, |count| {
+            (count < self.max).then(|| count + 1)
+        });
+    }
+
+    /// Returns number of permits left.
+    #[allow(dead_code)]
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn third_acquire_waits_for_release() {
+    let semaphore = Semaphore::new(2);
+    semaphore.acquire();
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+    assert_eq!(semaphore.available(), 0);
+
+    semaphore.release();
+    semaphore.acquire();
+    assert_eq!(semaphore.available(), 0);
+}
+
+#[test_case]
+fn release_is_capped_at_max() {
+    let semaphore = Semaphore::with_max(1, 2);
+    semaphore.release();
+    semaphore.release();
+    assert_eq!(semaphore.available(), 2);
+}
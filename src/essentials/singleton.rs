@@ -6,6 +6,7 @@
 /// inner.method();
 /// unsafe { SINGLETON.give(inner); }
 /// ```
+#[allow(dead_code)]
 pub struct Singleton<T> {
     pub inner: Option<T>,
 }
@@ -13,15 +14,56 @@ pub struct Singleton<T> {
 impl<T> Singleton<T> {
     /// Takes ownership of singleton's inner value.
     /// If the inner value is already taken, program crashes.
+    #[allow(dead_code)]
     pub fn take(&mut self) -> T {
-        let p = core::mem::replace(&mut self.inner, None);
-        // TODO: better double take handling
-        p.unwrap()
+        self.try_take().expect("singleton value already taken")
+    }
+
+    /// Takes ownership of singleton's inner value,
+    /// or returns `None` if it is already taken.
+    #[allow(dead_code)]
+    pub fn try_take(&mut self) -> Option<T> {
+        self.inner.take()
+    }
+
+    /// Returns whether the inner value can be taken.
+    #[allow(dead_code)]
+    pub fn is_available(&self) -> bool {
+        self.inner.is_some()
     }
 
     /// Returns taken value from singleton to the singleton
     /// for next use.
+    #[allow(dead_code)]
     pub fn give(&mut self, inner: T) {
-        let _ = core::mem::replace(&mut self.inner, Some(inner));
+        self.inner = Some(inner);
     }
 }
+
+#[test_case]
+fn try_take_after_give() {
+    let mut singleton = Singleton { inner: Some(1) };
+    assert!(singleton.is_available());
+
+    let inner = singleton.try_take();
+    assert_eq!(inner, Some(1));
+    assert!(!singleton.is_available());
+    assert_eq!(singleton.try_take(), None);
+
+    singleton.give(2);
+    assert!(singleton.is_available());
+    assert_eq!(singleton.try_take(), Some(2));
+}
+
+#[cfg(test)]
+fn double_take_panics() {
+    let mut singleton = Singleton { inner: Some(0) };
+    singleton.take();
+    singleton.take();
+}
+
+#[test_case]
+static DOUBLE_TAKE_PANICS: crate::test::ShouldPanic = crate::test::ShouldPanic {
+    name: concat!(module_path!(), "::double_take_panics"),
+    test: double_take_panics,
+};
@@ -0,0 +1,97 @@
+use core::ptr;
+
+/// Wrapper that makes every read and write of the inner value volatile,
+/// so the compiler can't elide or reorder accesses to memory-mapped devices.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct Volatile<T: Copy>(T);
+
+impl<T: Copy> Volatile<T> {
+    #[allow(dead_code)]
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Reads the inner value.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(&self.0) }
+    }
+
+    /// Overwrites the inner value with `value`.
+    #[inline]
+    pub fn write(&mut self, value: T) {
+        unsafe { ptr::write_volatile(&mut self.0, value) }
+    }
+
+    /// Reads the inner value, lets `f` modify it and writes it back,
+    /// with exactly one volatile read and one volatile write.
+    #[inline]
+    #[allow(dead_code)]
+    pub fn update(&mut self, f: impl FnOnce(&mut T)) {
+        let mut value = self.read();
+        f(&mut value);
+        self.write(value);
+    }
+}
+
+/// [`Volatile`] that can only be read, such as a status register.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct ReadOnly<T: Copy>(Volatile<T>);
+
+impl<T: Copy> ReadOnly<T> {
+    #[allow(dead_code)]
+    pub const fn new(value: T) -> Self {
+        Self(Volatile::new(value))
+    }
+
+    /// Reads the inner value.
+    #[inline]
+    #[allow(dead_code)]
+    pub fn read(&self) -> T {
+        self.0.read()
+    }
+}
+
+/// [`Volatile`] that can only be written, such as a command register.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct WriteOnly<T: Copy>(Volatile<T>);
+
+impl<T: Copy> WriteOnly<T> {
+    #[allow(dead_code)]
+    pub const fn new(value: T) -> Self {
+        Self(Volatile::new(value))
+    }
+
+    /// Overwrites the inner value with `value`.
+    #[inline]
+    #[allow(dead_code)]
+    pub fn write(&mut self, value: T) {
+        self.0.write(value)
+    }
+}
+
+#[test_case]
+fn update_modifies_once() {
+    let mut register = Volatile::new(0x10u32);
+    let mut calls = 0;
+    register.update(|value| {
+        calls += 1;
+        *value |= 1;
+    });
+    assert_eq!(calls, 1);
+    assert_eq!(register.read(), 0x11);
+}
+
+#[test_case]
+fn read_only_and_write_only() {
+    let status = ReadOnly::new(7u8);
+    assert_eq!(status.read(), 7);
+
+    let mut command = WriteOnly::new(0u16);
+    command.write(0xabcd);
+    // Same layout as the plain value.
+    assert_eq!(unsafe { *(&command as *const WriteOnly<u16> as *const u16) }, 0xabcd);
+}
@@ -0,0 +1,428 @@
+//! Allocation of physical memory frames.
+use core::ops::Range;
+
+use crate::essentials::Mutex;
+use crate::multiboot::{BootInfo, MemoryKind};
+
+/// Size of a physical frame in bytes.
+pub const FRAME_SIZE: u64 = 4096;
+
+/// Kernel-wide frame allocator, set up by [`init`].
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator<'static>>> = Mutex::new(None);
+
+/// Sets up [`FRAME_ALLOCATOR`] for the memory of `boot_info`.
+///
+/// # Safety
+/// Same as [`BitmapFrameAllocator::new`]. Must be called only once.
+pub unsafe fn init(boot_info: &BootInfo) {
+    *FRAME_ALLOCATOR.lock() = Some(BitmapFrameAllocator::new(boot_info));
+}
+
+/// Physical memory frame of [`FRAME_SIZE`] bytes, represented by its aligned start address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysFrame(u64);
+
+impl PhysFrame {
+    /// Returns frame containing physical `address`.
+    pub const fn containing_address(address: u64) -> Self {
+        Self(address & !(FRAME_SIZE - 1))
+    }
+
+    /// Returns physical address of the first byte of the frame.
+    pub const fn start_address(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Allocator of physical frames.
+pub trait FrameAllocator {
+    /// Returns a frame not allocated before, or `None` if there are no frames left.
+    fn allocate_frame(&mut self) -> Option<PhysFrame>;
+}
+
+/// Rounds `address` up to the next frame boundary.
+const fn align_up(address: u64) -> u64 {
+    (address + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)
+}
+
+extern "C" {
+    /// Start of the kernel image, defined in `link.ld`.
+    static _code: u8;
+    /// End of the kernel image, defined in `link.ld`.
+    static _end: u8;
+}
+
+/// Returns memory occupied by the kernel image, including its statics and the boot stack.
+pub fn kernel_image() -> Range<u64> {
+    unsafe { &_code as *const u8 as u64..&_end as *const u8 as u64 }
+}
+
+/// Number of ranges [`BootInfoFrameAllocator`] never allocates from.
+const RESERVED: usize = 4;
+
+/// Returns physical memory in use at boot: the kernel image and the multiboot structures.
+fn boot_reserved(boot_info: &BootInfo) -> [Range<u64>; RESERVED] {
+    let kernel = kernel_image();
+    let info_start = boot_info as *const BootInfo as u64;
+    let info = info_start..info_start + core::mem::size_of::<BootInfo>() as u64;
+    let command_line = boot_info.command_line().map_or(0..0, |command_line| {
+        let start = command_line.as_ptr() as u64;
+        // Including the NUL terminator.
+        start..start + command_line.len() as u64 + 1
+    });
+
+    [kernel, info, boot_info.memory_map_range(), command_line]
+}
+
+/// Returns whether `a` and `b` share at least one address.
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Frame allocator handing out `Available` frames of the multiboot memory map
+/// in increasing order. Frames can't be freed.
+pub struct BootInfoFrameAllocator<'a> {
+    boot_info: &'a BootInfo,
+    /// Physical memory in use before the allocator was created.
+    reserved: [Range<u64>; RESERVED],
+    /// Lowest address the next frame can start at.
+    next: u64,
+}
+
+impl<'a> BootInfoFrameAllocator<'a> {
+    /// Creates allocator that skips frames overlapping the kernel image
+    /// and the multiboot structures.
+    ///
+    /// # Safety
+    /// The memory map of `boot_info` must be correct and the kernel
+    /// must not use any other `Available` memory.
+    #[allow(dead_code)]
+    pub unsafe fn new(boot_info: &'a BootInfo) -> Self {
+        Self::with_reserved(boot_info, boot_reserved(boot_info))
+    }
+
+    /// Creates allocator that skips frames overlapping any of the `reserved` ranges.
+    ///
+    /// # Safety
+    /// Same as [`BootInfoFrameAllocator::new`], except that the kernel
+    /// may use the `reserved` memory.
+    #[allow(dead_code)]
+    pub unsafe fn with_reserved(boot_info: &'a BootInfo, reserved: [Range<u64>; RESERVED]) -> Self {
+        Self {
+            boot_info,
+            reserved,
+            next: 0,
+        }
+    }
+
+    /// Returns start of the lowest available frame at or above `self.next`.
+    fn next_available(&self) -> Option<u64> {
+        self.boot_info.memory_map()
+            .filter(|area| area.kind == MemoryKind::Available)
+            .filter_map(|area| {
+                let start = align_up(core::cmp::max(area.base_addr, self.next));
+                (start + FRAME_SIZE <= area.base_addr + area.length).then_some(start)
+            })
+            .min()
+    }
+}
+
+impl<'a> FrameAllocator for BootInfoFrameAllocator<'a> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        loop {
+            let start = self.next_available()?;
+            let frame = start..start + FRAME_SIZE;
+
+            match self.reserved.iter().find(|range| overlaps(range, &frame)) {
+                Some(range) => self.next = align_up(range.end),
+                None => {
+                    self.next = frame.end;
+                    return Some(PhysFrame(start));
+                }
+            }
+        }
+    }
+}
+
+/// Lowest address [`BitmapFrameAllocator::new`] stores the bitmap at. Memory below
+/// 1 MiB can hold data of the BIOS and the bootloader the memory map doesn't mention.
+const BITMAP_MIN_ADDRESS: u64 = 0x10_0000;
+
+/// Frame allocator tracking every frame in a bitmap, which lets frames be freed.
+pub struct BitmapFrameAllocator<'a> {
+    /// Bit `i % 8` of byte `i / 8` is set if frame `i` is in use.
+    bitmap: &'a mut [u8],
+    /// Lowest frame that may be free.
+    next: usize,
+}
+
+impl BitmapFrameAllocator<'static> {
+    /// Creates allocator for all `Available` memory of `boot_info`, except the kernel
+    /// image and the multiboot structures. The bitmap is stored in the first
+    /// available memory above 1 MiB big enough for it, which is marked used.
+    ///
+    /// # Safety
+    /// Same as [`BootInfoFrameAllocator::new`]. Physical memory must be identity mapped.
+    pub unsafe fn new(boot_info: &BootInfo) -> Self {
+        let reserved = boot_reserved(boot_info);
+        let size = Self::bitmap_size(boot_info) as u64;
+
+        let start = boot_info.memory_map()
+            .filter(|area| area.kind == MemoryKind::Available)
+            .find_map(|area| {
+                let end = area.base_addr + area.length;
+                let mut start = align_up(core::cmp::max(area.base_addr, BITMAP_MIN_ADDRESS));
+                while start + size <= end {
+                    match reserved.iter().find(|range| overlaps(range, &(start..start + size))) {
+                        Some(range) => start = align_up(range.end),
+                        None => return Some(start),
+                    }
+                }
+                None
+            })
+            .expect("no memory for the frame bitmap");
+
+        let bitmap = core::slice::from_raw_parts_mut(start as *mut u8, size as usize);
+        let [kernel, info, memory_map, command_line] = reserved;
+        // Frame 0 would be a null pointer once accessed.
+        let null = 0..FRAME_SIZE;
+        Self::with_bitmap(boot_info, bitmap, &[kernel, info, memory_map, command_line, start..start + size, null])
+    }
+}
+
+impl<'a> BitmapFrameAllocator<'a> {
+    /// Returns number of bitmap bytes needed for all `Available` memory of `boot_info`.
+    pub fn bitmap_size(boot_info: &BootInfo) -> usize {
+        let end = boot_info.memory_map()
+            .filter(|area| area.kind == MemoryKind::Available)
+            .map(|area| area.base_addr + area.length)
+            .max()
+            .unwrap_or(0);
+        let frames = (end / FRAME_SIZE) as usize;
+        frames.div_ceil(8)
+    }
+
+    /// Creates allocator keeping state in `bitmap`, whose frames are free if they
+    /// lie inside `Available` memory of `boot_info` and outside of all `reserved` ranges.
+    /// Frames beyond those `bitmap` covers are never allocated.
+    ///
+    /// # Safety
+    /// Same as [`BootInfoFrameAllocator::with_reserved`].
+    pub unsafe fn with_bitmap(boot_info: &BootInfo, bitmap: &'a mut [u8], reserved: &[Range<u64>]) -> Self {
+        bitmap.fill(0xff);
+        let mut allocator = Self { bitmap, next: 0 };
+
+        for area in boot_info.memory_map().filter(|area| area.kind == MemoryKind::Available) {
+            let mut start = align_up(area.base_addr);
+            while start + FRAME_SIZE <= area.base_addr + area.length {
+                let frame = start..start + FRAME_SIZE;
+                let index = (start / FRAME_SIZE) as usize;
+                if index < allocator.frames() && !reserved.iter().any(|range| overlaps(range, &frame)) {
+                    allocator.set_used(index, false);
+                }
+                start += FRAME_SIZE;
+            }
+        }
+        allocator
+    }
+
+    /// Returns number of frames covered by the bitmap.
+    fn frames(&self) -> usize {
+        self.bitmap.len() * 8
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set_used(&mut self, index: usize, used: bool) {
+        if used {
+            self.bitmap[index / 8] |= 1 << (index % 8);
+        } else {
+            self.bitmap[index / 8] &= !(1 << (index % 8));
+        }
+    }
+
+    /// Allocates `count` adjacent frames and returns the first one,
+    /// or `None` if there is no such free run.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+
+        let mut run_start = self.next;
+        let mut index = self.next;
+        while index < self.frames() {
+            if self.is_used(index) {
+                // Skip whole used bytes.
+                index = if index.is_multiple_of(8) && self.bitmap[index / 8] == 0xff { index + 8 } else { index + 1 };
+                run_start = index;
+                continue;
+            }
+
+            index += 1;
+            if index - run_start == count {
+                for frame in run_start..index {
+                    self.set_used(frame, true);
+                }
+                if run_start == self.next {
+                    self.next = index;
+                }
+                return Some(PhysFrame(run_start as u64 * FRAME_SIZE));
+            }
+        }
+        None
+    }
+
+    /// Frees `frame`, so it can be allocated again.
+    ///
+    /// # Panics
+    /// If `frame` isn't allocated.
+    #[allow(dead_code)]
+    pub fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = (frame.start_address() / FRAME_SIZE) as usize;
+        assert!(index < self.frames() && self.is_used(index), "freeing unused frame {:#x}", frame.start_address());
+
+        self.set_used(index, false);
+        self.next = core::cmp::min(self.next, index);
+    }
+}
+
+impl<'a> FrameAllocator for BitmapFrameAllocator<'a> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_contiguous(1)
+    }
+}
+
+#[test_case]
+fn phys_frame_addresses() {
+    assert_eq!(PhysFrame::containing_address(0x1234).start_address(), 0x1000);
+    assert_eq!(PhysFrame::containing_address(0x2000).start_address(), 0x2000);
+    assert_eq!(align_up(0x2001), 0x3000);
+    assert_eq!(align_up(0x2000), 0x2000);
+}
+
+#[test_case]
+fn frames_come_from_available_areas() {
+    use crate::multiboot::{MemoryArea, test_boot_info};
+
+    let areas = [
+        MemoryArea { base_addr: 0x0, length: 0x5000, kind: MemoryKind::Available },
+        MemoryArea { base_addr: 0x5000, length: 0x3000, kind: MemoryKind::Reserved },
+        // Not frame aligned.
+        MemoryArea { base_addr: 0x8800, length: 0x7800, kind: MemoryKind::Available },
+    ];
+    test_boot_info(&areas, |boot_info| {
+        let reserved = [0x1000..0x2800, 0..0, 0..0, 0..0];
+        let mut allocator = unsafe { BootInfoFrameAllocator::with_reserved(boot_info, reserved.clone()) };
+
+        let mut count = 0;
+        let mut last = None;
+        while let Some(frame) = allocator.allocate_frame() {
+            let start = frame.start_address();
+            assert_eq!(start % FRAME_SIZE, 0);
+            assert!(areas.iter().any(|area| area.kind == MemoryKind::Available
+                && area.base_addr <= start && start + FRAME_SIZE <= area.base_addr + area.length));
+            assert!(!(reserved[0].start < start + FRAME_SIZE && start < reserved[0].end));
+            assert!(last < Some(frame));
+            last = Some(frame);
+            count += 1;
+        }
+        // 0x0, 0x3000, 0x4000 and 0x9000 up to 0xf000.
+        assert_eq!(count, 3 + 7);
+    });
+}
+
+#[test_case]
+fn frames_skip_kernel_image() {
+    let boot_info = crate::multiboot::boot_info().unwrap();
+    let mut allocator = unsafe { BootInfoFrameAllocator::new(boot_info) };
+    let kernel = kernel_image();
+
+    for _ in 0..512 {
+        let frame = allocator.allocate_frame().unwrap();
+        assert!(!(kernel.start < frame.start_address() + FRAME_SIZE && frame.start_address() < kernel.end));
+    }
+}
+
+/// Calls `f` with the memory map of a 16 MiB machine: the usual hole below 1 MiB
+/// is reserved, everything else is available.
+#[cfg(test)]
+fn with_16_mib_map(f: impl FnOnce(&BootInfo)) {
+    use crate::multiboot::{MemoryArea, test_boot_info};
+
+    let areas = [
+        MemoryArea { base_addr: 0x0, length: 0x9f000, kind: MemoryKind::Available },
+        MemoryArea { base_addr: 0x9f000, length: 0x61000, kind: MemoryKind::Reserved },
+        MemoryArea { base_addr: 0x100000, length: 0xf00000, kind: MemoryKind::Available },
+    ];
+    test_boot_info(&areas, f);
+}
+
+#[test_case]
+fn bitmap_frames_are_reused() {
+    with_16_mib_map(|boot_info| {
+        assert_eq!(BitmapFrameAllocator::bitmap_size(boot_info), 16 * 1024 * 1024 / 4096 / 8);
+
+        let mut bitmap = [0; 512];
+        let reserved = 0x1000..0x2000;
+        let mut allocator = unsafe { BitmapFrameAllocator::with_bitmap(boot_info, &mut bitmap, core::slice::from_ref(&reserved)) };
+        let a = allocator.allocate_frame().unwrap();
+        let b = allocator.allocate_frame().unwrap();
+        assert_eq!((a.start_address(), b.start_address()), (0x0, 0x2000));
+
+        allocator.deallocate_frame(a);
+        assert_eq!(allocator.allocate_frame(), Some(a));
+
+        // 0x9e000 is the last frame before the hole.
+        let mut last = b;
+        while let Some(frame) = allocator.allocate_frame() {
+            assert!(frame > last);
+            assert!(!(0x9f000..0x100000).contains(&frame.start_address()));
+            last = frame;
+        }
+        assert_eq!(last.start_address(), 0xfff000);
+        allocator.deallocate_frame(PhysFrame(0x9e000));
+        assert_eq!(allocator.allocate_frame(), Some(PhysFrame(0x9e000)));
+    });
+}
+
+#[test_case]
+fn contiguous_frames_skip_used_gaps() {
+    with_16_mib_map(|boot_info| {
+        let mut bitmap = [0; 512];
+        let reserved = 0x3000..0x4000;
+        let mut allocator = unsafe { BitmapFrameAllocator::with_bitmap(boot_info, &mut bitmap, core::slice::from_ref(&reserved)) };
+
+        assert_eq!(allocator.allocate_contiguous(4), Some(PhysFrame(0x4000)));
+        assert_eq!(allocator.allocate_frame(), Some(PhysFrame(0x0)));
+
+        // Doesn't span the hole below 1 MiB.
+        let run = allocator.allocate_contiguous(0x90).unwrap();
+        assert_eq!(run.start_address(), 0x8000);
+        assert_eq!(allocator.allocate_contiguous(0x20), Some(PhysFrame(0x100000)));
+        assert_eq!(allocator.allocate_contiguous(0x1000), None);
+    });
+}
+
+#[test_case]
+fn bitmap_storage_is_not_allocated() {
+    use alloc::vec::Vec;
+
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let bitmap = allocator.bitmap.as_ptr() as u64..allocator.bitmap.as_ptr() as u64 + allocator.bitmap.len() as u64;
+    let kernel = kernel_image();
+
+    let mut frames = Vec::new();
+    while let Some(frame) = allocator.allocate_frame() {
+        let range = frame.start_address()..frame.start_address() + FRAME_SIZE;
+        assert!(range.start != 0 && !overlaps(&range, &bitmap) && !overlaps(&range, &kernel));
+        frames.push(frame);
+    }
+    assert!(!frames.is_empty());
+    // Leave the kernel-wide allocator as it was.
+    for frame in frames {
+        allocator.deallocate_frame(frame);
+    }
+}
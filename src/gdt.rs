@@ -0,0 +1,225 @@
+//! Global Descriptor Table (GDT) and task state segments (TSS).
+//!
+//! The kernel uses flat 4 GiB code and data segments. Protected mode has no
+//! interrupt stack table, so double faults are handled by a task gate
+//! switching to [`DOUBLE_FAULT_TSS`], which runs on its own stack.
+use core::arch::asm;
+use core::mem::size_of;
+use core::ptr::addr_of;
+
+/// Selector of the kernel code segment.
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+/// Selector of the kernel data segment.
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+/// Selector of the TSS of the kernel's main task.
+pub const TSS_SELECTOR: u16 = 0x18;
+/// Selector of the TSS of the double fault task.
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x20;
+/// Selector of an expand-down stack segment making pushes below
+/// `OVERFLOW_TEST_STACK` fault, only set up for tests.
+#[cfg(test)]
+const OVERFLOW_TEST_SELECTOR: u16 = 0x28;
+
+/// Size of the stack the double fault task runs on.
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 4096;
+
+/// 32-bit task state segment. The CPU saves a task's state into it on a task switch.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TaskStateSegment {
+    /// Selector of the previous task, set by the CPU when switching through a gate.
+    pub link: u32,
+    pub esp0: u32,
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldtr: u32,
+    pub trap: u16,
+    /// Offset of the I/O permission bitmap, beyond the limit means none.
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        Self {
+            link: 0, esp0: 0, ss0: 0, esp1: 0, ss1: 0, esp2: 0, ss2: 0,
+            cr3: 0, eip: 0, eflags: 0,
+            eax: 0, ecx: 0, edx: 0, ebx: 0, esp: 0, ebp: 0, esi: 0, edi: 0,
+            es: 0, cs: 0, ss: 0, ds: 0, fs: 0, gs: 0, ldtr: 0,
+            trap: 0,
+            iomap_base: size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+/// Flat ring 0 code segment: base 0, limit 4 GiB, 32-bit, execute/read.
+const KERNEL_CODE: u64 = 0x00cf_9a00_0000_ffff;
+/// Flat ring 0 data segment: base 0, limit 4 GiB, 32-bit, read/write.
+const KERNEL_DATA: u64 = 0x00cf_9200_0000_ffff;
+
+/// Returns descriptor of a 32-bit read/write expand-down data segment, whose valid
+/// offsets are `bottom` up to 4 GiB. `bottom` must be page aligned.
+#[cfg(test)]
+fn expand_down_descriptor(bottom: usize) -> u64 {
+    // With 4 KiB granularity the limit is the last invalid page.
+    let limit = (bottom as u64 >> 12) - 1;
+
+    let mut descriptor = limit & 0xffff;
+    // Present, ring 0, data, expand-down, writable.
+    descriptor |= 0x96 << 40;
+    descriptor |= ((limit >> 16) & 0xf) << 48;
+    // 4 KiB granularity, 32-bit.
+    descriptor |= 0xc << 52;
+    descriptor
+}
+
+/// Returns descriptor of an available 32-bit TSS at `tss`.
+fn tss_descriptor(tss: *const TaskStateSegment) -> u64 {
+    let base = tss as u64;
+    let limit = (size_of::<TaskStateSegment>() - 1) as u64;
+
+    let mut descriptor = limit & 0xffff;
+    descriptor |= (base & 0xff_ffff) << 16;
+    // Present, ring 0, available 32-bit TSS.
+    descriptor |= 0x89 << 40;
+    descriptor |= ((limit >> 16) & 0xf) << 48;
+    descriptor |= ((base >> 24) & 0xff) << 56;
+    descriptor
+}
+
+/// Task state of the kernel's main task, saved here when a double fault occurs.
+pub static mut TSS: TaskStateSegment = TaskStateSegment::new();
+/// Task state of the double fault task, loaded by the task gate of vector 8.
+pub static mut DOUBLE_FAULT_TSS: TaskStateSegment = TaskStateSegment::new();
+
+#[repr(C, align(4096))]
+struct Stack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+static mut DOUBLE_FAULT_STACK: Stack = Stack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+#[cfg(test)]
+static mut OVERFLOW_TEST_STACK: Stack = Stack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+/// The CPU sets the busy flag of TSS descriptors, so the table must be writable.
+static mut GDT: [u64; 6] = [0, KERNEL_CODE, KERNEL_DATA, 0, 0, 0];
+
+#[repr(C, packed)]
+struct DescriptorTablePointer {
+    size: u16,
+    base: usize,
+}
+
+/// Loads the GDT, reloads all segment registers and loads the main task's TSS.
+///
+/// Must be called once, before interrupts are enabled.
+pub fn init() {
+    unsafe {
+        let stack_top = addr_of!(DOUBLE_FAULT_STACK) as usize + DOUBLE_FAULT_STACK_SIZE;
+
+        DOUBLE_FAULT_TSS.eip = crate::interrupts::double_fault_task_entry() as u32;
+        DOUBLE_FAULT_TSS.esp = stack_top as u32;
+        DOUBLE_FAULT_TSS.cr3 = crate::registers::read_cr3() as u32;
+        // Only the reserved bit 1, interrupts stay disabled.
+        DOUBLE_FAULT_TSS.eflags = 0x2;
+        DOUBLE_FAULT_TSS.cs = KERNEL_CODE_SELECTOR as u32;
+        DOUBLE_FAULT_TSS.ss = KERNEL_DATA_SELECTOR as u32;
+        DOUBLE_FAULT_TSS.ds = KERNEL_DATA_SELECTOR as u32;
+        DOUBLE_FAULT_TSS.es = KERNEL_DATA_SELECTOR as u32;
+        DOUBLE_FAULT_TSS.fs = KERNEL_DATA_SELECTOR as u32;
+        DOUBLE_FAULT_TSS.gs = KERNEL_DATA_SELECTOR as u32;
+
+        GDT[(TSS_SELECTOR >> 3) as usize] = tss_descriptor(addr_of!(TSS));
+        GDT[(DOUBLE_FAULT_TSS_SELECTOR >> 3) as usize] = tss_descriptor(addr_of!(DOUBLE_FAULT_TSS));
+        #[cfg(test)]
+        {
+            GDT[(OVERFLOW_TEST_SELECTOR >> 3) as usize] =
+                expand_down_descriptor(addr_of!(OVERFLOW_TEST_STACK) as usize);
+        }
+
+        let pointer = DescriptorTablePointer {
+            size: (size_of::<[u64; 6]>() - 1) as u16,
+            base: addr_of!(GDT) as usize,
+        };
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+
+        // `cs` can only be reloaded by a far jump or return.
+        asm!(
+            "push {code}",
+            "lea {tmp}, [2f]",
+            "push {tmp}",
+            "retf",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov gs, {data:x}",
+            "mov ss, {data:x}",
+            code = in(reg) KERNEL_CODE_SELECTOR as usize,
+            data = in(reg) KERNEL_DATA_SELECTOR as usize,
+            tmp = out(reg) _,
+        );
+
+        asm!("ltr {:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    }
+}
+
+#[test_case]
+fn segments_are_reloaded() {
+    use crate::cpu;
+
+    let tr: u16;
+    unsafe {
+    asm!("str {:x}", out(reg) tr, options(nomem, nostack, preserves_flags));
+    }
+    assert_eq!(cpu::read_cs(), KERNEL_CODE_SELECTOR);
+    assert_eq!(cpu::read_ds(), KERNEL_DATA_SELECTOR);
+    assert_eq!(cpu::read_es(), KERNEL_DATA_SELECTOR);
+    assert_eq!(cpu::read_ss(), KERNEL_DATA_SELECTOR);
+    assert_eq!(tr, TSS_SELECTOR);
+    assert_eq!(size_of::<TaskStateSegment>(), 104);
+}
+
+/// Recurses forever on `OVERFLOW_TEST_STACK`.
+///
+/// The limit of the expand-down stack segment catches the overflow: the stack
+/// fault can't be delivered on the exhausted stack, which turns it into a double fault.
+#[cfg(test)]
+fn stack_overflow() {
+    unsafe {
+    asm!(
+        "cli",
+        "mov ss, {selector:x}",
+        "mov esp, {top}",
+        "2:",
+        "call 2b",
+        selector = in(reg) OVERFLOW_TEST_SELECTOR as usize,
+        top = in(reg) addr_of!(OVERFLOW_TEST_STACK) as usize + DOUBLE_FAULT_STACK_SIZE,
+        options(noreturn),
+    );
+    }
+}
+
+#[test_case]
+static STACK_OVERFLOW: crate::test::ShouldPanic = crate::test::ShouldPanic {
+    name: concat!(module_path!(), "::stack_overflow"),
+    test: stack_overflow,
+};
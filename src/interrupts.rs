@@ -1,10 +1,131 @@
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use bit_field::BitField;
 
-pub type HandlerFunc = extern "C" fn() -> !;
+/// Number of times each interrupt vector fired, indexed by vector and
+/// counted by the registered handlers with [`count`]. The target has no
+/// 64-bit atomics, so the counters wrap around at `usize::MAX`.
+pub static STATS: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
 
-#[derive(PartialEq, Eq)]
+/// Number of spurious IRQs, which are also counted in [`STATS`] under their vector.
+pub static SPURIOUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts an interrupt of `vector` in [`STATS`]. Called first by every handler.
+#[inline(always)]
+pub(crate) fn count(vector: u8) {
+    STATS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Prints how often every vector that fired at least once did so.
+pub fn print_stats() {
+    for (vector, count) in STATS.iter().enumerate() {
+        let count = count.load(Ordering::Relaxed);
+        if count != 0 {
+            crate::println!("vector {:3}: {}", vector, count);
+        }
+    }
+
+    let spurious = SPURIOUS.load(Ordering::Relaxed);
+    if spurious != 0 {
+        crate::println!("spurious:   {}", spurious);
+    }
+}
+
+/// Entry point of an interrupt without error code, as stored in the IDT.
+/// Created by [`handler!`], or by hand for trampolines passing more than the frame.
+#[derive(Clone, Copy)]
+pub struct HandlerFunc(pub(crate) unsafe extern "C" fn());
+
+/// Entry point of an exception that pushes an error code, as stored in the IDT.
+/// Created by [`handler_with_error_code!`].
+#[derive(Clone, Copy)]
+pub struct HandlerFuncWithErrCode(unsafe extern "C" fn());
+
+/// Values the CPU pushes on the stack when an interrupt occurs.
+///
+/// `stack_pointer` and `stack_segment` are only pushed when the interrupt
+/// changes privilege level, i.e. when interrupting userland code.
+/// For interrupted kernel code they hold whatever lies on the stack above the frame.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    /// Address of the instruction to return to.
+    pub instruction_pointer: usize,
+    /// Code segment selector of the interrupted code.
+    pub code_segment: usize,
+    /// `EFLAGS` of the interrupted code.
+    pub cpu_flags: usize,
+    /// Stack pointer of the interrupted code.
+    pub stack_pointer: usize,
+    /// Stack segment selector of the interrupted code.
+    pub stack_segment: usize,
+}
+
+/// Creates a [`HandlerFunc`] trampoline for `extern "C" fn(&InterruptStackFrame)` `$handler`.
+///
+/// The trampoline saves all general purpose registers, calls `$handler` with
+/// a reference to the interrupt stack frame and then restores the registers
+/// and returns with `iretd`.
+macro_rules! handler {
+    ($handler:ident) => {{
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($handler), "_trampoline"),
+            concat!(stringify!($handler), "_trampoline:"),
+            "pushad",
+            // The frame lies right above the 8 registers saved by `pushad`.
+            "lea eax, [esp + 32]",
+            "push eax",
+            "cld",
+            "call {handler}",
+            "add esp, 4",
+            "popad",
+            "iretd",
+            handler = sym $handler,
+        );
+        extern "C" {
+            #[link_name = concat!(stringify!($handler), "_trampoline")]
+            fn trampoline();
+        }
+        HandlerFunc(trampoline)
+    }};
+}
+
+/// Creates a [`HandlerFuncWithErrCode`] trampoline for
+/// `extern "C" fn(&InterruptStackFrame, usize)` `$handler`.
+///
+/// Works like [`handler!`], but additionally passes the error code the CPU
+/// pushed below the frame to `$handler` and removes it before `iretd`.
+macro_rules! handler_with_error_code {
+    ($handler:ident) => {{
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($handler), "_trampoline"),
+            concat!(stringify!($handler), "_trampoline:"),
+            "pushad",
+            // The error code lies right above the 8 registers saved by `pushad`
+            // and the frame right above the error code.
+            "mov eax, [esp + 32]",
+            "lea ecx, [esp + 36]",
+            "push eax",
+            "push ecx",
+            "cld",
+            "call {handler}",
+            "add esp, 8",
+            "popad",
+            // Pop the error code.
+            "add esp, 4",
+            "iretd",
+            handler = sym $handler,
+        );
+        extern "C" {
+            #[link_name = concat!(stringify!($handler), "_trampoline")]
+            fn trampoline();
+        }
+        HandlerFuncWithErrCode(trampoline)
+    }};
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TableIndex {
     /// Global Descriptor Table
     Gdt = 0,
@@ -24,7 +145,7 @@ pub enum TableIndex {
 /// `TI` - Table index; 0 = GDT, 1 = LDT
 ///
 /// `Index` - Index to a Descriptor of the table.
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Selector(u16);
 
 impl Selector {
@@ -32,67 +153,299 @@ impl Selector {
         Self(0)
     }
 
+    /// Creates selector from its raw value, e.g. one read from a segment register.
+    #[allow(dead_code)]
+    pub const fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw value, as loaded into a segment register.
+    #[allow(dead_code)]
+    pub const fn raw(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns Requested Privilege Level of `Selector`.
+    pub fn rpl(&self) -> u8 {
+        self.0.get_bits(0..=1) as u8
+    }
+
+    /// Returns table the `Index` points into.
+    pub fn table(&self) -> TableIndex {
+        if self.0.get_bit(2) { TableIndex::Ldt } else { TableIndex::Gdt }
+    }
+
+    /// Returns `Index` of the descriptor in its table.
+    pub fn index(&self) -> u16 {
+        self.0.get_bits(3..=15)
+    }
+
     /// Set Requested Privilage Level on `Selector`.
+    #[allow(dead_code)]
     pub fn set_rpl(mut self, rpl: u8) -> Self {
         self.0.set_bits(0..=1, rpl as u16);
         self
     }
 
+    #[allow(dead_code)]
     pub fn set_table_index(mut self, table_index: TableIndex) -> Self {
         self.0.set_bit(2, table_index == TableIndex::Ldt);
         self
     }
 
     /// Sets `Index` bits in `Selector` to given `index` to descriptor table.
+    #[allow(dead_code)]
     pub fn set_index(mut self, index: u16) -> Self {
         self.0.set_bits(3..=15, index);
         self
     }
 }
 
-fn get_code_segment() -> u16 {
-    let mut result: u16;
+impl core::fmt::Debug for Selector {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("Selector")
+            .field("index", &self.index())
+            .field("table", &self.table())
+            .field("rpl", &self.rpl())
+            .finish()
+    }
+}
+
+/// Formats as, for example, `GDT[2] RPL 0`.
+impl core::fmt::Display for Selector {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let table = match self.table() {
+            TableIndex::Gdt => "GDT",
+            TableIndex::Ldt => "LDT",
+        };
+        write!(f, "{}[{}] RPL {}", table, self.index(), self.rpl())
+    }
+}
+
+/// Enables hardware interrupts (`sti`).
+#[inline]
+pub fn enable() {
+    unsafe {
+    asm!("sti", options(nomem, nostack));
+    }
+}
+
+/// Disables hardware interrupts (`cli`).
+#[inline]
+pub fn disable() {
+    unsafe {
+    asm!("cli", options(nomem, nostack));
+    }
+}
+
+/// Halts the CPU until the next interrupt arrives (`hlt`).
+#[inline]
+pub fn hlt() {
+    unsafe {
+    asm!("hlt", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Raises a breakpoint exception (`int3`), which the breakpoint handler reports
+/// before execution continues right after this call.
+#[allow(dead_code)]
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe {
+    asm!("int3", options(nomem, nostack));
+    }
+}
+
+/// Prints the source location to serial and triggers [`breakpoint`].
+#[macro_export]
+macro_rules! debug_break {
+    () => ({
+        $crate::serial_println!("debug_break at {}:{}", file!(), line!());
+        $crate::interrupts::breakpoint();
+    });
+}
+
+/// Enables hardware interrupts and halts the CPU until the next one (`sti; hlt`).
+///
+/// `sti` takes effect only after the following instruction, so no interrupt
+/// can arrive between the two. Checking a condition with interrupts disabled and
+/// then calling this doesn't miss a wake up.
+#[inline]
+pub fn enable_and_hlt() {
+    unsafe {
+    asm!("sti", "hlt", options(nomem, nostack));
+    }
+}
+
+/// Halts the CPU forever, waking up only to handle interrupts.
+pub fn halt_loop() -> ! {
+    loop {
+        hlt();
+    }
+}
+
+/// Returns whether hardware interrupts are enabled,
+/// i.e. whether the interrupt flag (bit 9 of `EFLAGS`) is set.
+#[inline]
+pub fn are_enabled() -> bool {
+    let flags: usize;
     unsafe {
     asm!(
-        "mov {:x}, cs",
-        out(reg) result,
+        "pushfd",
+        "pop {}",
+        out(reg) flags,
+        options(nomem, preserves_flags),
     );
     }
+    flags.get_bit(9)
+}
+
+/// Runs `f` with interrupts disabled and afterwards restores the interrupt
+/// flag to the state it had before the call.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let were_enabled = are_enabled();
+    if were_enabled {
+        disable();
+    }
+
+    let result = f();
+
+    if were_enabled {
+        enable();
+    }
+
     result
 }
 
 /// Interrupt Descriptor Table
+#[allow(non_snake_case)]
 pub mod IDT {
     use super::*;
+    use crate::apic::apic_spurious_interrupt_handler;
+    use crate::apic::timer::apic_timer_interrupt_handler;
+    use crate::keyboard::keyboard_interrupt_handler;
+    use crate::pic::{spurious_irq_master_handler, spurious_irq_slave_handler};
+    use crate::pit::timer_interrupt_handler;
 
-    static IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
+    use crate::essentials::Mutex;
+    use lazy_static::lazy_static;
 
-        idt
-    };
+    lazy_static! {
+        /// The table loaded by [`init`]. Entries changed through [`modify`] take
+        /// effect right away, the CPU reads them on every interrupt.
+        static ref IDT: Mutex<InterruptDescriptorTable> = Mutex::new({
+            let mut idt = InterruptDescriptorTable::new();
+            idt.set_handler(0, handler!(divide_error_handler));
+            idt.set_handler(2, handler!(nmi_handler));
+            idt.set_handler(3, handler!(breakpoint_handler));
+            idt.set_handler(6, handler!(invalid_opcode_handler));
+            idt.set_task_gate(8, crate::gdt::DOUBLE_FAULT_TSS_SELECTOR);
+            idt.set_handler_with_error_code(13, handler_with_error_code!(general_protection_fault_handler));
+            idt.set_handler_with_error_code(14, handler_with_error_code!(page_fault_handler));
+
+            idt.set_handler(crate::pic::PIC1_OFFSET + crate::pit::TIMER_IRQ, handler!(timer_interrupt_handler));
+            idt.set_handler(crate::pic::PIC1_OFFSET + crate::keyboard::KEYBOARD_IRQ, handler!(keyboard_interrupt_handler));
+            idt.set_handler(crate::pic::PIC1_OFFSET + crate::pic::SPURIOUS_IRQ_MASTER, handler!(spurious_irq_master_handler));
+            idt.set_handler(crate::pic::PIC1_OFFSET + crate::pic::SPURIOUS_IRQ_SLAVE, handler!(spurious_irq_slave_handler));
+            idt.set_handler(crate::apic::TIMER_VECTOR, handler!(apic_timer_interrupt_handler));
+            idt.set_handler(crate::apic::SPURIOUS_VECTOR, handler!(apic_spurious_interrupt_handler));
+            // Userland must be able to make system calls.
+            idt.set_handler(crate::syscall::VECTOR, crate::syscall::entry())
+                .set_descriptor_privilage_level(DescriptorPrivilageLevel::Low);
+            idt
+        });
+    }
 
     pub fn init() {
-        IDT.load();
+        // The table lives in a static, so it never moves.
+        unsafe { IDT.lock().load() };
     }
 
-    pub struct InterruptDescriptorTable([Entry; 16]);
+    /// Runs `f` on the table with interrupts masked, for example to register a handler:
+    /// `IDT::modify(|idt| { idt.set_handler(vector, handler!(handler)); })`.
+    #[allow(dead_code)]
+    pub fn modify<R>(f: impl FnOnce(&mut InterruptDescriptorTable) -> R) -> R {
+        f(&mut IDT.lock_irqsafe())
+    }
+
+    /// Table with an entry for every one of the 256 x86 interrupt vectors.
+    pub struct InterruptDescriptorTable([Entry; 256]);
 
     impl InterruptDescriptorTable {
         const fn new() -> Self {
-            Self([Entry::missing(); 16])
+            Self([Entry::missing(); 256])
         }
 
-        fn set_handler(&mut self, entry_index: u8, handler: HandlerFunc) -> &mut TypeAttribute {
-            let selector = Selector::new().set_index(get_code_segment());
-            self.0[entry_index as usize] = Entry::new(selector, handler);
-            &mut self.0[entry_index as usize].type_attribute
+        /// Installs `handler` as present 32-bit interrupt gate with DPL 0
+        /// in the kernel code segment at `entry_index`.
+        /// The returned attributes can override the DPL.
+        pub fn set_handler(&mut self, entry_index: u8, handler: HandlerFunc) -> &mut TypeAttribute {
+            self.set_entry(entry_index, handler.0 as usize)
         }
 
-        fn load(&'static self) {
-            let ptr = DescriptorTablePointer {
+        /// Installs `handler` for an exception that pushes an error code
+        /// (vectors 8, 10–14 and 17), see [`set_handler`](Self::set_handler).
+        pub fn set_handler_with_error_code(&mut self, entry_index: u8, handler: HandlerFuncWithErrCode) -> &mut TypeAttribute {
+            self.set_entry(entry_index, handler.0 as usize)
+        }
+
+        /// Installs present task gate switching to the task of `tss_selector` at `entry_index`.
+        fn set_task_gate(&mut self, entry_index: u8, tss_selector: u16) -> &mut TypeAttribute {
+            // Task gates have no offset, the task's TSS holds its entry point.
+            self.0[entry_index as usize] = Entry::new(Selector(tss_selector), 0);
+            self.0[entry_index as usize].type_attribute
+                .set_present(true)
+                .set_gate(GateType::Task32)
+        }
+
+        fn set_entry(&mut self, entry_index: u8, handler_address: usize) -> &mut TypeAttribute {
+            // `cs` already holds a complete selector.
+            let selector = Selector(crate::cpu::read_cs());
+            let entry = &mut self.0[entry_index as usize];
+            *entry = Entry::new(selector, handler_address);
+            entry.type_attribute = TypeAttribute::interrupt_gate(DescriptorPrivilageLevel::High);
+            &mut entry.type_attribute
+        }
+
+        /// Returns attributes of the entry at `entry_index`.
+        #[allow(dead_code)]
+        pub fn type_attribute(&self, entry_index: u8) -> TypeAttribute {
+            self.0[entry_index as usize].type_attribute
+        }
+
+        /// Returns the entry at `entry_index`, to put it back with
+        /// [`set_raw_entry`](Self::set_raw_entry) after swapping in another handler.
+        // Only tests swap handlers so far.
+        #[allow(dead_code)]
+        pub fn entry(&self, entry_index: u8) -> Entry {
+            self.0[entry_index as usize]
+        }
+
+        /// Stores `entry` at `entry_index` as it is.
+        #[allow(dead_code)]
+        pub fn set_raw_entry(&mut self, entry_index: u8, entry: Entry) {
+            self.0[entry_index as usize] = entry;
+        }
+
+        /// Returns pointer describing this table to the `lidt` instruction.
+        fn pointer(&self) -> DescriptorTablePointer {
+            DescriptorTablePointer {
                 base: self as *const _ as usize,
+                // The limit is the offset of the last valid byte.
                 size: (core::mem::size_of::<Self>() - 1) as u16,
-            };
+            }
+        }
+
+        /// Makes the CPU use this table (`lidt`).
+        ///
+        /// # Safety
+        /// The table must stay at its address as long as it is loaded.
+        /// Reports an error if `sidt` doesn't return the loaded pointer.
+        unsafe fn load(&self) {
+            let ptr = self.pointer();
 
             unsafe {
             asm!(
@@ -100,15 +453,40 @@ pub mod IDT {
                 in(reg) &ptr,
             );
             }
+
+            let loaded = sidt();
+            if loaded != ptr {
+                crate::error!("IDT not loaded: sidt returned base {:#x} limit {:#x} instead of base {:#x} limit {:#x}",
+                              { loaded.base }, { loaded.size }, { ptr.base }, { ptr.size });
+            }
+        }
+    }
+
+    /// Returns limit of the loaded table as reported by `sidt`, its size minus one.
+    pub fn loaded_limit() -> u16 {
+        sidt().size
+    }
+
+    /// Returns pointer to the loaded table (`sidt`).
+    fn sidt() -> DescriptorTablePointer {
+        let mut pointer = DescriptorTablePointer { size: 0, base: 0 };
+        unsafe {
+        asm!("sidt [{}]", in(reg) &mut pointer, options(nostack, preserves_flags));
         }
+        pointer
     }
 
+    /// Operand of `lidt` and `sidt`: the limit directly followed by the base address.
+    #[derive(Clone, Copy, PartialEq, Eq)]
     #[repr(C,packed)]
     struct DescriptorTablePointer {
         size: u16,
         base: usize,
     }
 
+    // No padding may separate the fields: 6 bytes on 32-bit and 10 bytes on 64-bit.
+    const _: [(); 2 + core::mem::size_of::<usize>()] = [(); core::mem::size_of::<DescriptorTablePointer>()];
+
     #[derive(Debug, Copy, Clone)]
     #[repr(C, packed)]
     pub struct Entry {
@@ -125,10 +503,10 @@ pub mod IDT {
     }
 
     impl Entry {
-        pub fn new(selector: Selector, handler: HandlerFunc) -> Self {
-            let pointer = handler as usize;
+        pub fn new(selector: Selector, handler_address: usize) -> Self {
+            let pointer = handler_address;
             Entry {
-                selector: selector,
+                selector,
                 offset_lower: pointer as u16,
                 offset_higher: (pointer >> 16) as u16,
                 type_attribute: TypeAttribute::new(),
@@ -148,12 +526,14 @@ pub mod IDT {
     }
 
     /// 4 bit value
-    enum GateType {
+    pub enum GateType {
         /// 80386 32 bit task gate
         Task32 =  0b0101,
         /// 80286 16-bit interrupt gate
+        #[allow(dead_code)]
         Interrupt16 = 0b0110,
         /// 80286 16-bit trap gate
+        #[allow(dead_code)]
         Trap16 = 0b0111,
         /// 80386 32-bit interrupt gate
         Interrupt32 = 0b1110,
@@ -161,38 +541,411 @@ pub mod IDT {
         Trap32 = 0b1111,
     }
 
-    // Descriptor Privilage Level
-    enum DescriptorPrivilageLevel {
+    /// Descriptor Privilage Level, the least privileged ring allowed to use the gate with `int`.
+    pub enum DescriptorPrivilageLevel {
         /// Typically kernel.
         High   = 0b00,
+        #[allow(dead_code)]
         Medium = 0b01,
         /// Typically userland.
         Low    = 0b11,
     }
 
-    #[derive(Clone, Copy, Debug)]
-    struct TypeAttribute(u8);
+    /// Type and attributes byte of an [`Entry`]: present bit, DPL and gate type.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct TypeAttribute(u8);
 
     impl TypeAttribute {
         const fn new() -> Self {
             Self(0)
         }
 
+        /// Returns present 32-bit interrupt gate usable from ring `dpl`.
+        /// Interrupts stay disabled while its handler runs.
+        pub const fn interrupt_gate(dpl: DescriptorPrivilageLevel) -> Self {
+            Self::present_gate(GateType::Interrupt32, dpl)
+        }
+
+        /// Returns present 32-bit trap gate usable from ring `dpl`.
+        /// Unlike an interrupt gate it leaves the interrupt flag unchanged.
+        #[allow(dead_code)]
+        pub const fn trap_gate(dpl: DescriptorPrivilageLevel) -> Self {
+            Self::present_gate(GateType::Trap32, dpl)
+        }
+
+        const fn present_gate(gate: GateType, dpl: DescriptorPrivilageLevel) -> Self {
+            Self(1 << 7 | (dpl as u8) << 5 | gate as u8)
+        }
+
+        /// Returns the byte as stored in the descriptor.
+        #[allow(dead_code)]
+        pub const fn value(&self) -> u8 {
+            self.0
+        }
+
         /// **P**: Present bit. Must be set (1) for the descriptor to be valid.
-        fn set_present(&mut self, present: bool) -> &mut Self {
+        pub fn set_present(&mut self, present: bool) -> &mut Self {
             self.0.set_bit(7, present);
             self
         }
 
-        fn set_descriptor_privilage_level(&mut self, dpl: DescriptorPrivilageLevel) -> &mut Self {
+        pub fn set_descriptor_privilage_level(&mut self, dpl: DescriptorPrivilageLevel) -> &mut Self {
             self.0.set_bits(5..=6, dpl as u8);
             self
         }
 
-        fn set_gate(&mut self, gate: GateType) -> &mut Self {
+        pub fn set_gate(&mut self, gate: GateType) -> &mut Self {
             self.0.set_bits(0..=4, gate as u8);
             self
         }
     }
 
+    #[test_case]
+    fn idt_covers_all_vectors() {
+        // 32-bit protected mode gate descriptors are 8 bytes long.
+        assert_eq!(core::mem::size_of::<Entry>(), 8);
+        assert_eq!(core::mem::size_of::<InterruptDescriptorTable>(), 256 * 8);
+
+        let size = IDT.lock().pointer().size;
+        assert_eq!(size as usize, 256 * 8 - 1);
+    }
+
+    #[test_case]
+    fn loaded_pointer_is_read_back() {
+        init();
+        let loaded = sidt();
+        let expected = IDT.lock().pointer();
+        assert!(loaded == expected);
+        assert_eq!({ loaded.base }, &*IDT.lock() as *const InterruptDescriptorTable as usize);
+        assert_eq!({ loaded.size }, 256 * 8 - 1);
+    }
+
+    #[test_case]
+    fn gate_attributes() {
+        assert_eq!(TypeAttribute::interrupt_gate(DescriptorPrivilageLevel::High).value(), 0x8e);
+        assert_eq!(TypeAttribute::trap_gate(DescriptorPrivilageLevel::High).value(), 0x8f);
+        assert_eq!(TypeAttribute::interrupt_gate(DescriptorPrivilageLevel::Low).value(), 0xee);
+
+        let mut attribute = TypeAttribute::new();
+        attribute.set_present(true).set_gate(GateType::Interrupt32);
+        assert_eq!(attribute, TypeAttribute::interrupt_gate(DescriptorPrivilageLevel::High));
+    }
+
+    #[test_case]
+    fn registered_handler_is_present() {
+        use core::sync::atomic::AtomicBool;
+
+        static CALLED: AtomicBool = AtomicBool::new(false);
+        extern "C" fn test_vector_handler(_frame: &InterruptStackFrame) {
+            CALLED.store(true, Ordering::Relaxed);
+        }
+
+        let attribute = modify(|idt| {
+            idt.set_handler(0x81, handler!(test_vector_handler));
+            idt.type_attribute(0x81)
+        });
+        assert!(attribute.value().get_bit(7));
+        assert_eq!(attribute.value().get_bits(0..=4), GateType::Interrupt32 as u8);
+        assert_eq!(attribute.value().get_bits(5..=6), 0);
+
+        unsafe {
+        asm!("int 0x81");
+        }
+        assert!(CALLED.load(Ordering::Relaxed));
+    }
+}
+
+extern "C" fn divide_error_handler(frame: &InterruptStackFrame) -> ! {
+    count(0);
+    crate::println!("EXCEPTION: DIVIDE ERROR\n{:x?}", frame);
+    halt_loop()
+}
+
+/// Status of the sources of non-maskable interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NmiStatus {
+    /// System control port B (`0x61`). Bit 7 reports a memory parity error,
+    /// bit 6 an I/O channel check.
+    pub control_port_b: u8,
+}
+
+impl NmiStatus {
+    /// Reads the status port.
+    ///
+    /// The NMI disable bit 7 of the CMOS index port `0x70` can't be read back,
+    /// the port is write-only. The kernel never sets it, see [`crate::rtc`].
+    pub fn read() -> Self {
+        use crate::port::Port;
+
+        // Reading the port has no side effects.
+        Self { control_port_b: unsafe { Port::<u8>::new(0x61).read() } }
+    }
+}
+
+impl core::fmt::Display for NmiStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "port 0x61 = {:#04x}", self.control_port_b)?;
+        if self.control_port_b.get_bit(7) {
+            f.write_str(", memory parity error")?;
+        }
+        if self.control_port_b.get_bit(6) {
+            f.write_str(", I/O channel check")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports a non-maskable interrupt and returns.
+///
+/// An NMI can interrupt code holding any lock, even with interrupts disabled,
+/// so outputs that are locked are skipped instead of waited for.
+extern "C" fn nmi_handler(frame: &InterruptStackFrame) {
+    use core::fmt::Write;
+
+    count(2);
+    let status = NmiStatus::read();
+    if let Some(mut monitor) = crate::monitor::VGA::BUFFER.try_lock() {
+        let _ = writeln!(monitor, "NMI: {}\n{:x?}", status, frame);
+    }
+    if let Some(mut serial) = crate::serial::SERIAL1.try_lock() {
+        let _ = writeln!(serial, "NMI: {}\n{:x?}", status, frame);
+    }
+}
+
+/// Reports a breakpoint. The breakpoint exception is a trap, the saved instruction
+/// pointer already points after `int3`, so `iretd` continues execution there.
+extern "C" fn breakpoint_handler(frame: &InterruptStackFrame) {
+    count(3);
+    crate::println!("EXCEPTION: BREAKPOINT at {:#x}", frame.instruction_pointer);
+}
+
+extern "C" fn invalid_opcode_handler(frame: &InterruptStackFrame) -> ! {
+    count(6);
+    crate::println!("EXCEPTION: INVALID OPCODE\n{:x?}", frame);
+    halt_loop()
+}
+
+// Entry point of the double fault task. The task starts on its own stack,
+// with the error code as the only value on it.
+core::arch::global_asm!(
+    ".global double_fault_task",
+    "double_fault_task:",
+    "mov eax, [esp]",
+    "push eax",
+    "cld",
+    "call {handler}",
+    handler = sym double_fault_handler,
+);
+
+/// Returns address the double fault task starts at, see [`crate::gdt`].
+pub(crate) fn double_fault_task_entry() -> usize {
+    extern "C" {
+        fn double_fault_task();
+    }
+    double_fault_task as *const () as usize
+}
+
+/// Runs as the double fault task. Reports the state of the faulting task,
+/// which the CPU saved into its TSS, by panicking.
+extern "C" fn double_fault_handler(error_code: usize) -> ! {
+    count(8);
+    let tss = unsafe { crate::gdt::TSS };
+    // A page fault that couldn't be delivered left its address in `CR2`.
+    let address = crate::registers::read_cr2();
+    if crate::paging::is_guard_page(address) {
+        panic!("EXCEPTION: KERNEL STACK OVERFLOW\npage fault at {:#x} in the guard page below the stack, esp {:#x}",
+               address, tss.esp);
+    }
+    // The stack pointer and segment are valid here, they were saved by the task switch.
+    let frame = InterruptStackFrame {
+        instruction_pointer: tss.eip as usize,
+        code_segment: tss.cs as usize,
+        cpu_flags: tss.eflags as usize,
+        stack_pointer: tss.esp as usize,
+        stack_segment: tss.ss as usize,
+    };
+    panic!("EXCEPTION: DOUBLE FAULT (error code {:#x})\n{:x?}", error_code, frame);
+}
+
+extern "C" fn general_protection_fault_handler(frame: &InterruptStackFrame, error_code: usize) -> ! {
+    count(13);
+    crate::println!("EXCEPTION: GENERAL PROTECTION FAULT (error code {:#x})\n{:x?}", error_code, frame);
+    halt_loop()
+}
+
+/// Address [`page_fault_handler`] resumes execution at instead of halting, `0` if none.
+static PAGE_FAULT_RESUME: AtomicUsize = AtomicUsize::new(0);
+/// Address whose access caused the last page fault recovered from through [`PAGE_FAULT_RESUME`].
+static RECOVERED_FAULT_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads the `u32` at `address`. Returns the faulting address instead
+/// if the read causes a page fault, so unmapped memory can be probed.
+#[allow(dead_code)]
+pub fn try_read(address: usize) -> Result<u32, usize> {
+    let value: u32;
+    let read: usize;
+    unsafe {
+    asm!(
+        "lea {read}, [3f]",
+        "mov [{resume}], {read}",
+        "mov {value}, [{address}]",
+        "mov {read}, 1",
+        "jmp 4f",
+        // A page fault continues here, with all registers as they were before the read.
+        "3:",
+        "xor {read}, {read}",
+        "4:",
+        "mov dword ptr [{resume}], 0",
+        read = out(reg) read,
+        value = out(reg) value,
+        resume = in(reg) &PAGE_FAULT_RESUME as *const AtomicUsize,
+        address = in(reg) address,
+        options(nostack),
+    );
+    }
+
+    if read != 0 {
+        Ok(value)
+    } else {
+        Err(RECOVERED_FAULT_ADDRESS.load(Ordering::Relaxed))
+    }
+}
+
+/// Reports the page fault and halts, unless it happened in [`try_read`],
+/// which then continues on its error path.
+extern "C" fn page_fault_handler(frame: &mut InterruptStackFrame, error_code: usize) {
+    count(14);
+    let resume = PAGE_FAULT_RESUME.swap(0, Ordering::Relaxed);
+    if resume != 0 {
+        RECOVERED_FAULT_ADDRESS.store(crate::registers::read_cr2(), Ordering::Relaxed);
+        frame.instruction_pointer = resume;
+        return;
+    }
+
+    crate::println!("EXCEPTION: PAGE FAULT at {:#x}", crate::registers::read_cr2());
+    crate::println!("present: {}, write: {}, user: {}, reserved: {}, instruction fetch: {}",
+                    error_code.get_bit(0),
+                    error_code.get_bit(1),
+                    error_code.get_bit(2),
+                    error_code.get_bit(3),
+                    error_code.get_bit(4));
+    crate::println!("{:x?}", frame);
+    halt_loop()
+}
+
+#[test_case]
+fn breakpoints_continue_and_are_counted() {
+    const N: usize = 3;
+    let before = STATS[3].load(Ordering::Relaxed);
+    for _ in 0..N {
+        unsafe {
+        asm!("int3");
+        }
+    }
+    breakpoint();
+    crate::debug_break!();
+    // Reaching this shows execution continued after every trap.
+    assert_eq!(STATS[3].load(Ordering::Relaxed) - before, N + 2);
+}
+
+#[test_case]
+fn selector_fields_round_trip() {
+    let selector = Selector::new().set_index(5).set_table_index(TableIndex::Ldt).set_rpl(3);
+    assert_eq!((selector.index(), selector.table(), selector.rpl()), (5, TableIndex::Ldt, 3));
+    assert_eq!(Selector::from_raw(selector.raw()), selector);
+    assert_eq!(alloc::format!("{}", selector), "LDT[5] RPL 3");
+
+    let code = Selector::from_raw(crate::gdt::KERNEL_CODE_SELECTOR);
+    assert_eq!((code.index(), code.table(), code.rpl()), (1, TableIndex::Gdt, 0));
+    assert_eq!(alloc::format!("{:?}", code), "Selector { index: 1, table: Gdt, rpl: 0 }");
+}
+
+#[test_case]
+fn nmi_status_is_formatted() {
+    use core::fmt::Write;
+
+    struct Buffer {
+        bytes: [u8; 128],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    let mut buffer = Buffer { bytes: [0; 128], len: 0 };
+    write!(buffer, "{}", NmiStatus { control_port_b: 0xc0 }).unwrap();
+    assert_eq!(&buffer.bytes[..buffer.len],
+               &b"port 0x61 = 0xc0, memory parity error, I/O channel check"[..]);
+
+    // The handler returns like the breakpoint one.
+    let before = STATS[2].load(Ordering::Relaxed);
+    unsafe {
+    asm!("int 2");
+    }
+    assert_eq!(STATS[2].load(Ordering::Relaxed) - before, 1);
+}
+
+#[test_case]
+fn error_code_reaches_handler() {
+    use core::sync::atomic::AtomicUsize;
+
+    /// Selector far beyond the end of the GDT, loading it faults with itself as error code.
+    const INVALID_SELECTOR: usize = 0xfff8;
+
+    static ERROR_CODE: AtomicUsize = AtomicUsize::new(0);
+    static RESUME: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn recover_general_protection(frame: &mut InterruptStackFrame, error_code: usize) {
+        ERROR_CODE.store(error_code, Ordering::SeqCst);
+        frame.instruction_pointer = RESUME.load(Ordering::SeqCst);
+    }
+
+    let previous = IDT::modify(|idt| {
+        let previous = idt.entry(13);
+        idt.set_handler_with_error_code(13, handler_with_error_code!(recover_general_protection));
+        previous
+    });
+
+    // The faulting `mov` leaves `ds` unchanged, execution continues after it.
+    unsafe {
+    asm!(
+        "lea {tmp}, [2f]",
+        "mov [{resume}], {tmp}",
+        "mov ds, {selector:x}",
+        "2:",
+        tmp = out(reg) _,
+        resume = in(reg) &RESUME as *const AtomicUsize,
+        selector = in(reg) INVALID_SELECTOR,
+        options(nostack),
+    );
+    }
+
+    IDT::modify(|idt| idt.set_raw_entry(13, previous));
+    assert_eq!(ERROR_CODE.load(Ordering::SeqCst), INVALID_SELECTOR);
+}
+
+#[test_case]
+fn nested_without_interrupts() {
+    let were_enabled = are_enabled();
+
+    enable();
+    without_interrupts(|| {
+        assert!(!are_enabled());
+        without_interrupts(|| assert!(!are_enabled()));
+        assert!(!are_enabled());
+    });
+    assert!(are_enabled());
+
+    disable();
+    without_interrupts(|| assert!(!are_enabled()));
+    assert!(!are_enabled());
+
+    if were_enabled {
+        enable();
+    }
 }
@@ -0,0 +1,573 @@
+//! Driver for the PS/2 keyboard.
+//!
+//! The IRQ handler queues raw (set 1) scancodes which are then read with [`read_scancode`].
+//! It also decodes them to characters queued in [`STDIN`], which are read with
+//! [`getchar`], [`try_getchar`] or [`read_line`].
+//! Keys are translated in the layout selected with [`set_layout`].
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::essentials::Mutex;
+use crate::interrupts::{self, InterruptStackFrame};
+use crate::pic;
+use crate::port::PortReadOnly;
+
+/// IRQ line the keyboard is connected to.
+pub const KEYBOARD_IRQ: u8 = 1;
+/// Scancode announcing that the next scancode belongs to an extended key.
+pub const EXTENDED_PREFIX: u8 = 0xe0;
+
+const DATA: PortReadOnly<u8> = PortReadOnly::new(0x60);
+
+/// Scancodes received but not read yet.
+static SCANCODES: ScancodeQueue<128> = ScancodeQueue::new();
+/// Whether the last received scancode was [`EXTENDED_PREFIX`].
+static PENDING_PREFIX: AtomicBool = AtomicBool::new(false);
+/// Modifier state of the keyboard, only used by the IRQ handler.
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+/// Characters typed but not read yet.
+///
+/// Lock it with [`Mutex::lock_irqsafe`], the IRQ handler pushes to it.
+pub static STDIN: Mutex<CharQueue<256>> = Mutex::new(CharQueue::new());
+
+/// Bit set in the scancode of a key release.
+const RELEASED: u8 = 0x80;
+
+const LEFT_CTRL: u8 = 0x1d;
+const LEFT_SHIFT: u8 = 0x2a;
+const RIGHT_SHIFT: u8 = 0x36;
+const CAPS_LOCK: u8 = 0x3a;
+/// Keypad enter, when extended.
+const KEYPAD_ENTER: u8 = 0x1c;
+/// Keypad slash, when extended.
+const KEYPAD_SLASH: u8 = 0x35;
+/// Up arrow, when extended. Keypad 8 otherwise.
+const UP_ARROW: u8 = 0x48;
+/// Down arrow, when extended. Keypad 2 otherwise.
+const DOWN_ARROW: u8 = 0x50;
+
+/// Character of the up arrow key, the same as Ctrl+P.
+pub const UP: char = '\x10';
+/// Character of the down arrow key, the same as Ctrl+N.
+pub const DOWN: char = '\x0e';
+
+/// Number of scancodes covered by the tables of a [`TableKeyMap`], up to the space bar.
+const TABLE_LEN: usize = 0x3a;
+
+/// US QWERTY layout, the default one.
+pub const US_QWERTY: TableKeyMap = TableKeyMap {
+    unshifted: b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ",
+    shifted: b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ",
+};
+
+/// German QWERTZ layout, without the keys needing AltGr or beyond the space bar.
+pub const DE_QWERTZ: TableKeyMap = TableKeyMap {
+    unshifted: b"\0\x1b1234567890\xdf\xb4\x08\tqwertzuiop\xfc+\n\0asdfghjkl\xf6\xe4^\0#yxcvbnm,.-\0*\0 ",
+    shifted: b"\0\x1b!\"\xa7$%&/()=?`\x08\tQWERTZUIOP\xdc*\n\0ASDFGHJKL\xd6\xc4\xb0\0'YXCVBNM;:_\0*\0 ",
+};
+
+/// State of the modifier keys a [`KeyMap`] translates with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    /// Either Shift key is held.
+    pub shift: bool,
+    /// Either Ctrl key is held.
+    pub ctrl: bool,
+    pub caps_lock: bool,
+}
+
+/// Keyboard layout, translating keys to characters for [`Decoder`].
+pub trait KeyMap: Sync {
+    /// Returns character of the pressed non-extended key `scancode` under
+    /// `modifiers`, `None` for keys without a character.
+    fn translate(&self, scancode: u8, modifiers: Modifiers) -> Option<char>;
+}
+
+/// Layout given by characters indexed by scancode, `0` for keys without a character.
+///
+/// Bytes above `0x7f` are Latin-1, whose characters are the first 256 of Unicode.
+/// Ctrl + letter gives the matching control code, Caps Lock only affects
+/// ASCII letters and is undone by Shift.
+pub struct TableKeyMap {
+    pub unshifted: &'static [u8; TABLE_LEN],
+    /// Characters with Shift held.
+    pub shifted: &'static [u8; TABLE_LEN],
+}
+
+impl KeyMap for TableKeyMap {
+    fn translate(&self, scancode: u8, modifiers: Modifiers) -> Option<char> {
+        let plain = *self.unshifted.get(scancode as usize)?;
+        if plain == 0 {
+            return None;
+        }
+
+        let byte = if plain.is_ascii_lowercase() {
+            if modifiers.ctrl {
+                return Some((plain & 0x1f) as char);
+            }
+            if modifiers.shift != modifiers.caps_lock { plain.to_ascii_uppercase() } else { plain }
+        } else if modifiers.shift {
+            self.shifted[scancode as usize]
+        } else {
+            plain
+        };
+        Some(byte as char)
+    }
+}
+
+/// Lock-free ring buffer of `N` scancodes for one producer (the IRQ handler)
+/// and one consumer.
+///
+/// When the queue is full, newly pushed scancodes are dropped.
+/// `N` should be a power of two, so indices stay continuous when the
+/// counters wrap around.
+pub struct ScancodeQueue<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    /// Number of scancodes ever pushed.
+    head: AtomicUsize,
+    /// Number of scancodes ever popped.
+    tail: AtomicUsize,
+}
+
+// The producer only writes slots the consumer has already released and
+// the consumer only reads slots the producer has published.
+unsafe impl<const N: usize> Sync for ScancodeQueue<N> {}
+
+impl<const N: usize> ScancodeQueue<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns number of free slots.
+    fn free(&self) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        N - head.wrapping_sub(tail)
+    }
+
+    /// Appends `scancodes` as a whole. Returns `false` and appends nothing
+    /// if there isn't enough space for all of them.
+    pub fn push(&self, scancodes: &[u8]) -> bool {
+        if self.free() < scancodes.len() {
+            return false;
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        for (i, &scancode) in scancodes.iter().enumerate() {
+            unsafe { (*self.buffer.get())[head.wrapping_add(i) % N] = scancode };
+        }
+        self.head.store(head.wrapping_add(scancodes.len()), Ordering::Release);
+        true
+    }
+
+    /// Removes and returns the oldest scancode or `None` if the queue is empty.
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let scancode = unsafe { (*self.buffer.get())[tail % N] };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(scancode)
+    }
+}
+
+/// Ring buffer of up to `N` characters.
+///
+/// When the queue is full, newly pushed characters are dropped, so what was
+/// typed first is kept, like with [`ScancodeQueue`].
+pub struct CharQueue<const N: usize> {
+    buffer: [char; N],
+    /// Index of the oldest character.
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> CharQueue<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: ['\0'; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns number of queued characters.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Appends `c`. Returns `false` and drops `c` if the queue is full.
+    pub fn push(&mut self, c: char) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buffer[(self.start + self.len) % N] = c;
+        self.len += 1;
+        true
+    }
+
+    /// Removes and returns the oldest character or `None` if the queue is empty.
+    pub fn pop(&mut self) -> Option<char> {
+        if self.is_empty() {
+            return None;
+        }
+        let c = self.buffer[self.start];
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+/// Translates scancodes to characters while tracking state of the modifier keys.
+pub struct Decoder {
+    left_shift: bool,
+    right_shift: bool,
+    ctrl: bool,
+    caps_lock: bool,
+    /// Whether Caps Lock is held, its typematic repeats mustn't toggle `caps_lock`.
+    caps_lock_down: bool,
+    /// Whether the previous scancode was [`EXTENDED_PREFIX`].
+    extended: bool,
+    layout: &'static dyn KeyMap,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            left_shift: false,
+            right_shift: false,
+            ctrl: false,
+            caps_lock: false,
+            caps_lock_down: false,
+            extended: false,
+            layout: &US_QWERTY,
+        }
+    }
+
+    /// Makes keys translate according to `layout`, [`US_QWERTY`] by default.
+    pub fn set_layout(&mut self, layout: &'static dyn KeyMap) {
+        self.layout = layout;
+    }
+
+    /// Feeds `scancode` to the decoder. Returns the character of a pressed key,
+    /// `None` for releases, modifiers and keys without a character.
+    ///
+    /// Control keys map to ASCII control characters (Enter is `'\n'`, Backspace `'\x08'`),
+    /// Ctrl + letter to the matching control code and the up and down arrows to [`UP`] and [`DOWN`].
+    pub fn decode(&mut self, scancode: u8) -> Option<char> {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+            return None;
+        }
+
+        let extended = core::mem::replace(&mut self.extended, false);
+        let pressed = scancode & RELEASED == 0;
+        let code = scancode & !RELEASED;
+
+        match (extended, code) {
+            // Right Ctrl is the extended left one.
+            (_, LEFT_CTRL) => self.ctrl = pressed,
+            (false, LEFT_SHIFT) => self.left_shift = pressed,
+            (false, RIGHT_SHIFT) => self.right_shift = pressed,
+            (false, CAPS_LOCK) => {
+                self.caps_lock ^= pressed && !self.caps_lock_down;
+                self.caps_lock_down = pressed;
+            },
+            (true, KEYPAD_ENTER) if pressed => return Some('\n'),
+            (true, KEYPAD_SLASH) if pressed => return Some('/'),
+            (true, UP_ARROW) if pressed => return Some(UP),
+            (true, DOWN_ARROW) if pressed => return Some(DOWN),
+            (false, _) if pressed => return self.layout.translate(code, self.modifiers()),
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns current state of the modifier keys.
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            ctrl: self.ctrl,
+            caps_lock: self.caps_lock,
+        }
+    }
+}
+
+/// Makes typed keys translate according to `layout`, [`US_QWERTY`] by default.
+pub fn set_layout(layout: &'static dyn KeyMap) {
+    DECODER.lock_irqsafe().set_layout(layout);
+}
+
+/// Unmasks the keyboard IRQ.
+pub fn init() {
+    pic::clear_mask(KEYBOARD_IRQ);
+}
+
+/// Returns the oldest received scancode, if any.
+///
+/// An [`EXTENDED_PREFIX`] is always immediately followed by the rest of its sequence.
+#[allow(dead_code)]
+pub fn read_scancode() -> Option<u8> {
+    SCANCODES.pop()
+}
+
+/// Returns the oldest typed character, if any.
+#[allow(dead_code)]
+pub fn try_getchar() -> Option<char> {
+    STDIN.lock_irqsafe().pop()
+}
+
+/// Returns the oldest typed character, halting until one is typed.
+///
+/// Interrupts must be enabled, otherwise no character ever arrives.
+pub fn getchar() -> char {
+    loop {
+        interrupts::disable();
+        if let Some(c) = STDIN.lock().pop() {
+            interrupts::enable();
+            return c;
+        }
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Reads typed characters into `buf` until Enter, echoing them to the display.
+/// Returns the number of bytes read, without the newline.
+///
+/// Backspace removes the last byte. Characters not fitting into `buf`
+/// and characters other than ASCII are ignored.
+#[allow(dead_code)]
+pub fn read_line(buf: &mut [u8]) -> usize {
+    read_line_with(buf, |_, _| None)
+}
+
+/// Reads a line like [`read_line`], but [`UP`] and [`DOWN`] are passed to `recall`
+/// together with `buf`. If it returns the length of a line it stored in `buf`,
+/// that line replaces the one typed so far.
+pub fn read_line_with(buf: &mut [u8], recall: impl FnMut(char, &mut [u8]) -> Option<usize>) -> usize {
+    edit_line(buf, getchar, |c| crate::print!("{}", c), recall)
+}
+
+/// Line editing of [`read_line_with`], reading characters from `next` and echoing with `echo`.
+fn edit_line(buf: &mut [u8], mut next: impl FnMut() -> char, mut echo: impl FnMut(char),
+             mut recall: impl FnMut(char, &mut [u8]) -> Option<usize>) -> usize {
+    let mut len = 0;
+    loop {
+        match next() {
+            '\n' => {
+                echo('\n');
+                return len;
+            },
+            c @ (UP | DOWN) => if let Some(recalled) = recall(c, buf) {
+                for _ in 0..len {
+                    echo('\x08');
+                }
+                len = core::cmp::min(recalled, buf.len());
+                buf[..len].iter().for_each(|&byte| echo(byte as char));
+            },
+            '\x08' => if len > 0 {
+                len -= 1;
+                echo('\x08');
+            },
+            c if c.is_ascii() && len < buf.len() => {
+                buf[len] = c as u8;
+                len += 1;
+                echo(c);
+            },
+            _ => {},
+        }
+    }
+}
+
+pub(crate) extern "C" fn keyboard_interrupt_handler(_frame: &InterruptStackFrame) {
+    interrupts::count(pic::PIC1_OFFSET + KEYBOARD_IRQ);
+    // Reading the data port acknowledges the scancode.
+    handle_scancode(unsafe { DATA.read() });
+    pic::send_eoi(KEYBOARD_IRQ);
+}
+
+/// Queues received `scancode` and the character it produces.
+/// Interrupts must be disabled.
+pub(crate) fn handle_scancode(scancode: u8) {
+    // Hold a prefix back until the rest of its sequence arrives,
+    // so consumers never see a split sequence.
+    if scancode == EXTENDED_PREFIX {
+        PENDING_PREFIX.store(true, Ordering::Relaxed);
+    } else if PENDING_PREFIX.swap(false, Ordering::Relaxed) {
+        SCANCODES.push(&[EXTENDED_PREFIX, scancode]);
+    } else {
+        SCANCODES.push(&[scancode]);
+    }
+
+    if let Some(c) = DECODER.lock().decode(scancode) {
+        STDIN.lock().push(c);
+    }
+}
+
+#[test_case]
+fn scancode_queue_full_and_empty() {
+    let queue = ScancodeQueue::<4>::new();
+    assert_eq!(queue.pop(), None);
+
+    assert!(queue.push(&[1, 2, 3]));
+    assert!(!queue.push(&[4, 5]));
+    assert!(queue.push(&[4]));
+    assert!(!queue.push(&[5]));
+
+    for scancode in 1..=4 {
+        assert_eq!(queue.pop(), Some(scancode));
+    }
+    assert_eq!(queue.pop(), None);
+}
+
+#[test_case]
+fn scancode_queue_wraps_around() {
+    let queue = ScancodeQueue::<4>::new();
+    for round in 0..10u8 {
+        assert!(queue.push(&[round, round + 100]));
+        assert_eq!(queue.pop(), Some(round));
+        assert_eq!(queue.pop(), Some(round + 100));
+    }
+    assert_eq!(queue.pop(), None);
+}
+
+#[test_case]
+fn char_queue_drops_newest_when_full() {
+    let mut queue = CharQueue::<3>::new();
+    assert_eq!(queue.pop(), None);
+
+    assert!(queue.push('a') && queue.push('b') && queue.push('c'));
+    assert!(queue.is_full());
+    assert!(!queue.push('d'));
+    assert_eq!(queue.pop(), Some('a'));
+
+    // Wraps around to the freed slot.
+    assert!(queue.push('e'));
+    assert_eq!(queue.len(), 3);
+    for c in ['b', 'c', 'e'] {
+        assert_eq!(queue.pop(), Some(c));
+    }
+    assert!(queue.is_empty());
+    assert_eq!(queue.pop(), None);
+}
+
+#[test_case]
+fn edit_line_handles_backspace() {
+    let mut typed = "ab\x08\x08\x08cdef\n".chars();
+    let mut echoed = 0;
+    let mut buf = [0; 3];
+
+    let len = edit_line(&mut buf, || typed.next().unwrap(), |_| echoed += 1, |_, _| None);
+    assert_eq!(&buf[..len], b"cde");
+    // The third backspace and "f" are not echoed.
+    assert_eq!(echoed, 2 + 2 + 3 + 1);
+}
+
+#[test_case]
+fn decode_letter_case() {
+    let mut decoder = Decoder::new();
+    // a
+    assert_eq!(decoder.decode(0x1e), Some('a'));
+    assert_eq!(decoder.decode(0x9e), None);
+    // Shift+a
+    assert_eq!(decoder.decode(0x2a), None);
+    assert_eq!(decoder.decode(0x1e), Some('A'));
+    assert_eq!(decoder.decode(0x9e), None);
+    assert_eq!(decoder.decode(0xaa), None);
+    assert_eq!(decoder.decode(0x1e), Some('a'));
+}
+
+#[test_case]
+fn decode_modifiers() {
+    let mut decoder = Decoder::new();
+    // Caps Lock toggles on press only and doesn't affect digits.
+    decoder.decode(CAPS_LOCK);
+    decoder.decode(CAPS_LOCK | RELEASED);
+    assert_eq!(decoder.decode(0x1e), Some('A'));
+    assert_eq!(decoder.decode(0x02), Some('1'));
+    decoder.decode(RIGHT_SHIFT);
+    assert_eq!(decoder.decode(0x1e), Some('a'));
+    assert_eq!(decoder.decode(0x02), Some('!'));
+    decoder.decode(RIGHT_SHIFT | RELEASED);
+
+    // Right Ctrl+c
+    decoder.decode(EXTENDED_PREFIX);
+    decoder.decode(LEFT_CTRL);
+    assert_eq!(decoder.decode(0x2e), Some('\x03'));
+    decoder.decode(EXTENDED_PREFIX);
+    decoder.decode(LEFT_CTRL | RELEASED);
+
+    assert_eq!(decoder.decode(0x1c), Some('\n'));
+    assert_eq!(decoder.decode(0x0e), Some('\x08'));
+
+    decoder.decode(EXTENDED_PREFIX);
+    assert_eq!(decoder.decode(UP_ARROW), Some(UP));
+    // Keypad 8 without Num Lock handling.
+    assert_eq!(decoder.decode(UP_ARROW), None);
+}
+
+#[test_case]
+fn held_caps_lock_toggles_once() {
+    let mut decoder = Decoder::new();
+    // A held key repeats its make code.
+    decoder.decode(CAPS_LOCK);
+    decoder.decode(CAPS_LOCK);
+    decoder.decode(CAPS_LOCK | RELEASED);
+    assert_eq!(decoder.decode(0x1e), Some('A'));
+
+    decoder.decode(CAPS_LOCK);
+    decoder.decode(CAPS_LOCK | RELEASED);
+    assert_eq!(decoder.decode(0x1e), Some('a'));
+}
+
+#[test_case]
+fn layouts_translate() {
+    let none = Modifiers::default();
+    let shift = Modifiers { shift: true, ..Modifiers::default() };
+    let ctrl = Modifiers { ctrl: true, ..Modifiers::default() };
+
+    // The key right of T.
+    assert_eq!(US_QWERTY.translate(0x15, none), Some('y'));
+    assert_eq!(DE_QWERTZ.translate(0x15, none), Some('z'));
+    assert_eq!(DE_QWERTZ.translate(0x15, shift), Some('Z'));
+    assert_eq!(DE_QWERTZ.translate(0x15, ctrl), Some('\x1a'));
+    assert_eq!(US_QWERTY.translate(0x15, ctrl), Some('\x19'));
+
+    assert_eq!(DE_QWERTZ.translate(0x1a, none), Some('\u{fc}'));
+    assert_eq!(DE_QWERTZ.translate(0x0c, none), Some('\u{df}'));
+    assert_eq!(DE_QWERTZ.translate(0x03, shift), Some('"'));
+    assert_eq!(DE_QWERTZ.translate(0x27, shift), Some('\u{d6}'));
+    // Left Shift has no character.
+    assert_eq!(US_QWERTY.translate(0x2a, none), None);
+}
+
+#[test_case]
+fn layouts_are_swapped() {
+    let mut decoder = Decoder::new();
+    // The key right of T.
+    assert_eq!(decoder.decode(0x15), Some('y'));
+    decoder.set_layout(&DE_QWERTZ);
+    assert_eq!(decoder.decode(0x15), Some('z'));
+    assert_eq!(decoder.decode(0x1a), Some('\u{fc}'));
+
+    decoder.decode(LEFT_SHIFT);
+    assert_eq!(decoder.decode(0x03), Some('"'));
+    assert_eq!(decoder.decode(0x2c), Some('Y'));
+    decoder.decode(LEFT_SHIFT | RELEASED);
+}
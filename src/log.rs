@@ -0,0 +1,131 @@
+//! Leveled logging to both the serial port and the VGA display.
+//!
+//! Messages are written with [`log!`](crate::log!) or the per level macros
+//! [`error!`](crate::error!), [`warn!`](crate::warn!), [`info!`](crate::info!)
+//! and [`debug!`](crate::debug!), which append a newline.
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::monitor::VGA::{self, Color};
+
+/// Logs formatted message at the given level: `log!(Level::Info, "fmt", args...)`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ($crate::log::_log($level, format_args!($($arg)*)));
+}
+
+/// Logs formatted message at [`Level::Error`](crate::log::Level::Error).
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+/// Logs formatted message at [`Level::Warn`](crate::log::Level::Warn).
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+/// Logs formatted message at [`Level::Info`](crate::log::Level::Info).
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+/// Logs formatted message at [`Level::Debug`](crate::log::Level::Debug).
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// Priority of a message, more important levels compare lower.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl Level {
+    /// Returns prefix of messages at this level.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    /// Returns foreground color of messages at this level on the display.
+    const fn color(self) -> Color {
+        match self {
+            Level::Error => Color::Red,
+            Level::Warn => Color::Yellow,
+            Level::Info => Color::White,
+            Level::Debug => Color::Grey,
+        }
+    }
+
+    const fn from_u8(value: u8) -> Level {
+        match value {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+/// Least important level that is logged.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Makes messages less important than `level` be dropped.
+#[allow(dead_code)]
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns least important level that is logged, [`Level::Info`] by default.
+pub fn max_level() -> Level {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Writes `args` prefixed by the name of `level` to serial and VGA,
+/// unless `level` is above [`max_level`].
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    if level > max_level() {
+        return;
+    }
+
+    crate::serial_println!("[{}] {}", level.name(), args);
+    VGA::_print_colored(level.color(), Color::Black, format_args!("[{}] {}\n", level.name(), args));
+}
+
+#[test_case]
+fn max_level_filters_messages() {
+    use VGA::BUFFER;
+
+    let saved = max_level();
+    set_max_level(Level::Warn);
+
+    let start = BUFFER.lock_irqsafe().cursor_position();
+    crate::info!("hidden");
+    assert_eq!(BUFFER.lock_irqsafe().cursor_position(), start);
+
+    BUFFER.lock_irqsafe().set_cursor_position(0, 3);
+    crate::error!("shown {}", 1);
+    {
+        let monitor = BUFFER.lock_irqsafe();
+        assert_eq!(monitor.cursor_position(), (0, 4));
+        let row: [u8; 15] = core::array::from_fn(|x| monitor.read_char_at(x, 3));
+        assert_eq!(&row, b"[ERROR] shown 1");
+        assert_eq!(monitor.read_attr_at(0, 3) & 0x0f, Color::Red as u8);
+    }
+
+    set_max_level(saved);
+    BUFFER.lock_irqsafe().set_cursor_position(start.0, start.1);
+}
@@ -9,16 +9,39 @@
 
 #![feature(const_mut_refs)]
 #![feature(const_raw_ptr_deref)]
+#![feature(alloc_error_handler)]
 
 #![warn(missing_docs)]
 
+extern crate alloc;
 extern crate bit_field;
 
 
 mod monitor;
+mod allocator;
+mod apic;
+mod console;
+mod cpu;
 mod essentials;
+mod frame_allocator;
+mod gdt;
 mod interrupts;
+mod keyboard;
+mod log;
+mod mmio;
+mod multiboot;
+mod paging;
+mod panic;
+mod pic;
+mod pit;
 mod port;
+mod registers;
+mod rtc;
+mod selftest;
+mod serial;
+mod shell;
+mod syscall;
+#[cfg(test)]
 mod test;
 
 // dev profile: easier to debug panics; can put a breakpoint on `rust_begin_unwind`
@@ -31,55 +54,144 @@ mod test;
 
 use core::panic::PanicInfo;
 
+/// Prints `info` and dumps registers and a backtrace to serial, then prints
+/// `info` in red on the display unless the panicking code holds its lock.
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(info: &PanicInfo<'_>) -> ! {
+    use monitor::VGA::Color;
+
+    let registers = panic::Registers::capture();
+    interrupts::disable();
+    panic::set_panicking(true);
+
+    serial_println!("KERNEL PANIC: {}", info);
+    panic::dump(&registers);
+    monitor::VGA::_try_print_colored(Color::Red, Color::Black, format_args!("KERNEL PANIC: {}\n", info));
+    interrupts::halt_loop()
+}
+
+/// Fails the running test, unless it should panic, and exits QEMU.
+#[cfg(test)]
 #[panic_handler]
 fn panic(info: &PanicInfo<'_>) -> ! {
-    println!("{}", info);
-    loop {}
+    let registers = panic::Registers::capture();
+    interrupts::disable();
+    panic::set_panicking(true);
+    panic::dump(&registers);
+
+    if test::is_expecting_panic() {
+        test_println!("[ok]");
+        test::exit_qemu(test::QemuExitCode::Success);
+        interrupts::halt_loop()
+    }
+
+    test_println!("[failed]");
+    test_println!("{}", info);
+    test::exit_qemu(test::QemuExitCode::Failed);
+    interrupts::halt_loop()
 }
 
 #[lang = "eh_personality"]
 extern "C" fn eh_personality() {}
 
-static HELLO: &str = "Hello\tWöorld\n";
-
-/// Initial kernel function that gets called by `src/boot.s`.
+/// Initial kernel function that gets called by `src/boot.s`
+/// with the magic value and the multiboot information structure left by the bootloader.
+// Only `boot.s` calls it, with the pointer the bootloader left.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(magic: u32, info: *const multiboot::BootInfo) -> ! {
+    // Panics with a wrong magic, which also fails and exits a test run.
+    let boot_info = unsafe { multiboot::init(magic, info) };
+    unsafe { allocator::init(core::ptr::addr_of_mut!(allocator::HEAP_SPACE) as usize, allocator::HEAP_SIZE) };
+    unsafe { frame_allocator::init(boot_info) };
+    // `serial=on` mirrors everything printed to COM1.
+    if boot_info.command_line_options().any(|option| option == ("serial", Some("on"))) {
+        console::set_console(console::console().with(&*serial::SERIAL1));
+    }
 
+    gdt::init();
     interrupts::IDT::init();
+    // Debug builds check core subsystems before using them.
+    #[cfg(all(debug_assertions, not(test)))]
+    selftest::run();
+    let memory_end = boot_info.memory_map()
+        .filter(|area| area.kind == multiboot::MemoryKind::Available)
+        .map(|area| area.base_addr + area.length)
+        .max()
+        .unwrap_or(0);
+    unsafe { paging::init(memory_end, frame_allocator::FRAME_ALLOCATOR.lock().as_mut().unwrap()) }
+        .expect("enabling paging failed");
+    unsafe { paging::install_guard_page(paging::kernel_stack_bottom()) }
+        .expect("installing the stack guard page failed");
+    pic::remap(pic::PIC1_OFFSET, pic::PIC2_OFFSET);
+    // Drivers unmask the IRQs they handle.
+    pic::set_masks(0xff, 0xff);
+    pit::init();
+    keyboard::init();
+    // `keymap=de` switches the keyboard to the German layout.
+    if boot_info.command_line_options().any(|option| option == ("keymap", Some("de"))) {
+        keyboard::set_layout(&keyboard::DE_QWERTZ);
+    }
+    interrupts::enable();
+    // `timer=apic` replaces the PIT ticks with ones of the APIC timer.
+    if boot_info.command_line_options().any(|option| option == ("timer", Some("apic"))) {
+        apic::timer::init(pit::DEFAULT_FREQUENCY);
+    }
 
-    // let mut writer = BUFFER.lock();
-
-    // writer.set_background_color(&Color::Black);
-    // writer.set_foreground_color(&Color::White);
-
-    // writer.clear();
-
-    // writer.write_str(HELLO);
-    // println!("{}", HELLO);
+    let available: u64 = boot_info.memory_map()
+        .filter(|area| area.kind == multiboot::MemoryKind::Available)
+        .map(|area| area.length)
+        .sum();
+    info!("{} KiB of memory available", available / 1024);
 
     #[cfg(test)]
     test_main();
 
-    loop {}
+    #[cfg(not(test))]
+    shell::run();
+    #[cfg(test)]
+    interrupts::halt_loop();
 }
 
 #[cfg(test)]
-fn test_runner(tests: &[&dyn Fn()]) {
+fn test_runner(tests: &[&dyn test::Testable]) {
     use test::*;
 
-    println!("Running {} tests", tests.len());
-    for test in tests {
-        test();
+    test_println!("Running {} tests", tests.len());
+    for test in tests.iter().filter(|test| !test.should_panic()) {
+        test.run();
+    }
+
+    // Passing a should-panic test exits QEMU, so at most one can run.
+    let selected = multiboot::boot_info()
+        .and_then(|info| info.command_line_options().find(|(key, _)| *key == "should_panic"))
+        .and_then(|(_, name)| name)
+        .unwrap_or("");
+    let count = tests.iter().filter(|test| test.should_panic()).count();
+    if count > 0 {
+        match tests.iter().find(|test| test.should_panic() && test.name().contains(selected)) {
+            Some(test) => {
+                if count > 1 {
+                    test_println!("Skipping {} should-panic tests, select one with should_panic=<name>", count - 1);
+                }
+                test.run();
+            }
+            None => {
+                test_println!("No should-panic test matches {:?}", selected);
+                test::exit_qemu(QemuExitCode::Failed);
+            }
+        }
     }
 
-    test::exit_qemu(QemuExitCode::Failed);
+    // A failing test panics, so getting here means all tests passed.
+    test::exit_qemu(QemuExitCode::Success);
 }
 
+/// Checks that the test runner runs tests at all.
 #[test_case]
+#[allow(clippy::eq_op)]
 fn trivial_assertion() {
-    print!("trivial assertion... ");
     assert_eq!(1, 1);
-    println!("[ok]");
 }
 
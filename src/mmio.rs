@@ -0,0 +1,46 @@
+//! Volatile access to memory-mapped I/O.
+use core::ptr;
+
+/// Memory-mapped I/O register holding a `T`.
+///
+/// Counterpart of [`Port`](crate::port::Port) for the memory address space.
+/// Every access is volatile, so the compiler can neither elide nor reorder it.
+pub struct Mmio<T: Copy> {
+    address: *mut T,
+}
+
+impl<T: Copy> Mmio<T> {
+    /// Creates register accessing memory at `address`.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be valid and aligned for reads and writes of `T`
+    /// for the whole lifetime of the returned `Mmio`.
+    pub const unsafe fn new(address: *mut T) -> Self {
+        Self {
+            address,
+        }
+    }
+
+    /// Reads the current value of the register.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.address) }
+    }
+
+    /// Writes `value` to the register.
+    #[inline]
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.address, value) }
+    }
+}
+
+#[test_case]
+fn mmio_round_trip() {
+    let mut cell: u32 = 0;
+    let register = unsafe { Mmio::new(&mut cell as *mut u32) };
+
+    register.write(0xdead_beef);
+    assert_eq!(register.read(), 0xdead_beef);
+    assert_eq!(cell, 0xdead_beef);
+}
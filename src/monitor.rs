@@ -2,50 +2,185 @@
 #[allow(non_snake_case)]
 pub mod VGA {
 
+    /// Prints to the consoles selected by [`crate::console::set_console`],
+    /// the VGA display by default.
     #[macro_export]
     macro_rules! print {
-        ($($arg:tt)*) => ($crate::monitor::VGA::_print(format_args!($($arg)*)));
+        ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
     }
 
+    /// Like [`print!`], but appends a newline.
     #[macro_export]
     macro_rules! println {
         () => ($crate::print!("\n"));
         ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
     }
 
+    /// Prints formatted text at column `x` and row `y` without moving the cursor:
+    /// `print_at!(x, y, "fmt", args...)`.
+    #[macro_export]
+    macro_rules! print_at {
+        ($x:expr, $y:expr, $($arg:tt)*) => ($crate::monitor::VGA::_print_at($x, $y, format_args!($($arg)*)));
+    }
+
+    /// Prints formatted text in the given colors without changing the colors
+    /// used by later prints: `print_colored!(fg, bg, "fmt", args...)`.
+    #[macro_export]
+    macro_rules! print_colored {
+        ($fg:expr, $bg:expr, $($arg:tt)*) => ($crate::monitor::VGA::_print_colored($fg, $bg, format_args!($($arg)*)));
+    }
+
+    /// Like [`print_colored!`], but appends a newline.
+    #[macro_export]
+    macro_rules! println_colored {
+        ($fg:expr, $bg:expr) => ($crate::print_colored!($fg, $bg, "\n"));
+        ($fg:expr, $bg:expr, $($arg:tt)*) => ($crate::print_colored!($fg, $bg, "{}\n", format_args!($($arg)*)));
+    }
+
+    /// Prints `value` in hexadecimal without the `fmt` machinery: `print_hex!(value)`.
+    #[macro_export]
+    macro_rules! print_hex {
+        ($value:expr) => ($crate::monitor::VGA::_print_hex($value as u64));
+    }
+
+    /// Like [`print_hex!`], but appends a newline.
+    #[macro_export]
+    macro_rules! println_hex {
+        ($value:expr) => ({
+            $crate::print_hex!($value);
+            $crate::monitor::VGA::_print(format_args!("\n"));
+        });
+    }
+
+    /// Writes `args` to [`BUFFER`].
+    ///
+    /// Interrupts are masked for the duration of each call, so an interrupt
+    /// handler that prints can't deadlock on `BUFFER` held by the code it preempted.
     #[doc(hidden)]
     pub fn _print(args: fmt::Arguments) {
         use core::fmt::Write;
-        BUFFER.lock().write_fmt(args).unwrap();
+        // Fails only at the end of a screen with scrolling disabled.
+        let _ = BUFFER.lock_irqsafe().write_fmt(args);
+    }
+
+    /// Writes `args` to [`BUFFER`] like [`_print`], but gives up instead of waiting
+    /// if it is locked. Returns whether `args` was written.
+    ///
+    /// For the panic handlers: the panicking code may hold the lock, which
+    /// then is never released.
+    #[doc(hidden)]
+    pub fn _try_print(args: fmt::Arguments) -> bool {
+        use core::fmt::Write;
+        match BUFFER.try_lock() {
+            Some(mut monitor) => {
+                let _ = monitor.write_fmt(args);
+                true
+            },
+            None => false,
+        }
     }
 
-    /// VGA display width in number of characters.
+    /// Writes `args` to [`BUFFER`] like [`_print_colored`], but gives up instead
+    /// of waiting if it is locked, see [`_try_print`].
+    #[doc(hidden)]
+    pub fn _try_print_colored(fg: Color, bg: Color, args: fmt::Arguments) -> bool {
+        use core::fmt::Write;
+        match BUFFER.try_lock() {
+            Some(mut monitor) => {
+                let (saved_fg, saved_bg) = (monitor.foreground_color, monitor.background_color);
+                monitor.foreground_color = fg;
+                monitor.background_color = bg;
+                let _ = monitor.write_fmt(args);
+                monitor.foreground_color = saved_fg;
+                monitor.background_color = saved_bg;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Writes `value` to [`BUFFER`] with [`Monitor::write_hex`].
+    #[doc(hidden)]
+    pub fn _print_hex(value: u64) {
+        BUFFER.lock_irqsafe().write_hex(value);
+    }
+
+    /// Writes `args` to [`BUFFER`] starting at column `x` and row `y`,
+    /// then moves the cursor back to where it was.
+    ///
+    /// The lock is taken once for the whole operation, so no other print
+    /// can interleave with the positioned one.
+    #[doc(hidden)]
+    pub fn _print_at(x: usize, y: usize, args: fmt::Arguments) {
+        use core::fmt::Write;
+        let mut monitor = BUFFER.lock_irqsafe();
+        let (saved_x, saved_y) = monitor.cursor_position();
+
+        monitor.set_cursor_position(x, y);
+        let _ = monitor.write_fmt(args);
+        monitor.set_cursor_position(saved_x, saved_y);
+    }
+
+    /// Writes `args` to [`BUFFER`] in foreground color `fg` and background
+    /// color `bg`, restoring the previous colors afterwards.
+    #[doc(hidden)]
+    pub fn _print_colored(fg: Color, bg: Color, args: fmt::Arguments) {
+        use core::fmt::Write;
+        let mut monitor = BUFFER.lock_irqsafe();
+        let (saved_fg, saved_bg) = (monitor.foreground_color, monitor.background_color);
+
+        monitor.foreground_color = fg;
+        monitor.background_color = bg;
+        let _ = monitor.write_fmt(args);
+        monitor.foreground_color = saved_fg;
+        monitor.background_color = saved_bg;
+    }
+
+    /// VGA display width in number of characters, in the default 80x25 text mode.
     const COLUMNS: usize = 80;
-    /// VGA display height in number of characters.
+    /// VGA display height in number of characters, in the default 80x25 text mode.
     const ROWS: usize    = 25;
     const TAB_WIDTH: usize = 8;
 
-    use crate::essentials::Mutex;
+    use crate::console::Console;
+    use crate::essentials::{Mutex, Volatile};
     use lazy_static::lazy_static;
 
     lazy_static!{
         /// Main singleton for writing to vga display.
-        pub static ref BUFFER: Mutex<Monitor> = Mutex::new(Monitor {
-            cursor: Cursor {
-                x: 0,
-                y: 0,
-            },
-            buffer: unsafe { &mut *(0xb8000 as *mut [[u16; COLUMNS];ROWS]) },
-            background_color: Color::Black,
-            foreground_color: Color::White,
+        pub static ref BUFFER: Mutex<Monitor> = Mutex::new({
+            let dimensions = BufferDimensions { cols: COLUMNS, rows: ROWS };
+            let mut monitor = unsafe { Monitor::new(0xb8000 as *mut u16, dimensions) };
+            // The BIOS leaves the hardware cursor shown.
+            monitor.cursor.visible = true;
+            monitor
         });
     }
 
+    /// Size of a text buffer in characters.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BufferDimensions {
+        pub cols: usize,
+        pub rows: usize,
+    }
+
+    impl BufferDimensions {
+        /// Returns number of cells of the buffer.
+        pub const fn cells(&self) -> usize {
+            self.cols * self.rows
+        }
+
+        /// Returns offset of the cell at column `x` and row `y` in the buffer, row by row.
+        const fn index(&self, x: usize, y: usize) -> usize {
+            y * self.cols + x
+        }
+    }
+
     /// Enum used to represent background and foreground color
     /// in vga display.
     #[allow(dead_code)]
     #[repr(u8)]
-    #[derive(Copy, Clone, Debug)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     pub enum Color {
         Black       = 0,
         Blue        = 1,
@@ -65,6 +200,64 @@ pub mod VGA {
         White       = 15,
     }
 
+    /// Bit of a color distinguishing its bright from its dim variant.
+    const INTENSITY: u8 = 0x8;
+
+    impl Color {
+        /// Returns color with value `nibble`, or `None` if `nibble` is above `15`.
+        pub const fn from_nibble(nibble: u8) -> Option<Color> {
+            let color = match nibble {
+                0  => Color::Black,
+                1  => Color::Blue,
+                2  => Color::Green,
+                3  => Color::Cyan,
+                4  => Color::Red,
+                5  => Color::Purple,
+                6  => Color::Brown,
+                7  => Color::Grey,
+                8  => Color::DarkGrey,
+                9  => Color::LightBlue,
+                10 => Color::LightGreen,
+                11 => Color::LightCyan,
+                12 => Color::LightRed,
+                13 => Color::LightPurple,
+                14 => Color::Yellow,
+                15 => Color::White,
+                _  => return None,
+            };
+            Some(color)
+        }
+
+        /// Returns the bright variant of the color, e.g. `LightBlue` for `Blue`.
+        /// Bright colors are returned unchanged.
+        #[allow(dead_code)]
+        pub const fn bright(self) -> Color {
+            match Color::from_nibble(self as u8 | INTENSITY) {
+                Some(color) => color,
+                None => self,
+            }
+        }
+
+        /// Returns the dim variant of the color, the inverse of [`Color::bright`].
+        /// Dim colors are returned unchanged.
+        #[allow(dead_code)]
+        pub const fn dim(self) -> Color {
+            match Color::from_nibble(self as u8 & !INTENSITY) {
+                Some(color) => color,
+                None => self,
+            }
+        }
+    }
+
+    impl TryFrom<u8> for Color {
+        /// The rejected value.
+        type Error = u8;
+
+        fn try_from(value: u8) -> Result<Self, Self::Error> {
+            Color::from_nibble(value).ok_or(value)
+        }
+    }
+
     /// Constructs vga character (`u16`) from `byte`, foreground and background color.
     ///
     /// `byte` is stored in `0x00ff`
@@ -73,103 +266,631 @@ pub mod VGA {
     ///
     /// `background_color` in `0xf000`
     const fn vga_char(byte: u8, background_color: Color, foreground_color: Color) -> u16 {
-        let attribute_byte: u8 = ( ( (background_color as u8) << 4)
-                                   | ( (foreground_color as u8) & 0x0f) ) as u8;
-        let character: u16 = ( (byte as u16) | ( (attribute_byte as u16) << 8) ) as u16;
-        character
+        let attribute_byte: u8 = ( (background_color as u8) << 4)
+                                 | ( (foreground_color as u8) & 0x0f);
+        (byte as u16) | ( (attribute_byte as u16) << 8)
     }
 
+    /// Character replacing the last one [`Monitor::write_str_clamped`] prints
+    /// of a string cut off, `'°'` in code page 437.
+    pub const TRUNCATION_MARK: u8 = 0xf8;
+
     /// Returns whether `byte` is a printable character.
     const fn is_printable(byte: u8) -> bool {
         32 <= byte && byte <= 126
     }
 
+    /// Prints `len` bytes starting at `addr` as a classic hex dump.
+    ///
+    /// Every line holds 16 bytes: the address of the first byte, the bytes
+    /// in hex and an ASCII gutter where non-printable bytes are shown as `.`.
+    ///
+    /// # Safety
+    ///
+    /// This is a debugging aid and does no bounds checking whatsoever.
+    /// Every byte in `addr..addr + len` gets read, so the caller must make
+    /// sure the whole range is readable memory.
+    // A debugging aid, nothing in the kernel calls it.
+    #[allow(dead_code)]
+    pub unsafe fn hexdump(addr: *const u8, len: usize) {
+        const BYTES_PER_LINE: usize = 16;
+
+        let mut offset = 0;
+        while offset < len {
+            let line_len = core::cmp::min(BYTES_PER_LINE, len - offset);
+
+            print!("{:08x}  ", addr as usize + offset);
+            for i in 0..BYTES_PER_LINE {
+                if i < line_len {
+                    print!("{:02x} ", *addr.add(offset + i));
+                } else {
+                    print!("   ");
+                }
+            }
+
+            print!("|");
+            for i in 0..line_len {
+                let byte = *addr.add(offset + i);
+                if is_printable(byte) {
+                    print!("{}", byte as char);
+                } else {
+                    print!(".");
+                }
+            }
+            println!("|");
+
+            offset += BYTES_PER_LINE;
+        }
+    }
+
     /// Inner display cursor representation.
     #[derive(Default, Debug)]
     struct Cursor {
         /// Current column position in given row.
-        /// [`COLUMNS`] is the maximum value. When reached `x` is reset to `0`
+        /// The number of columns is the maximum value. When reached `x` is reset to `0`
         /// and `y` is increased.
         x: usize,
         /// Current row position in vga display.
-        /// [`ROWS`] is the maximum value. When reached [`scroll()`](self::Monitor::scroll) function is called
+        /// The number of rows is the maximum value. When reached [`scroll()`](self::Monitor::scroll) function is called
         /// and cursor gets set to begginning of last row.
         y: usize,
+        /// Whether the blinking hardware cursor is shown.
+        visible: bool,
     }
 
+    /// Registers of the VGA CRT controller, selected through an index port.
+    struct CrtController {
+        index: crate::port::PortWriteOnly<u8>,
+        data: crate::port::PortWriteOnly<u8>,
+    }
+
+    /// CRT controller at its color mode ports.
+    const CRTC: CrtController = CrtController {
+        index: crate::port::PortWriteOnly::new(0x3d4),
+        data: crate::port::PortWriteOnly::new(0x3d5),
+    };
+
+    /// CRT controller register: cursor start scanline and disable bit.
+    const CURSOR_START: u8 = 0x0a;
+    /// CRT controller register: cursor end scanline.
+    const CURSOR_END: u8 = 0x0b;
+    /// CRT controller register: high byte of the cursor position.
+    const CURSOR_HIGH: u8 = 0x0e;
+    /// CRT controller register: low byte of the cursor position.
+    const CURSOR_LOW: u8 = 0x0f;
+
+    impl CrtController {
+        /// Writes `value` to the cursor register `register`, one of the `CURSOR_*` constants.
+        fn write_cursor_register(&self, register: u8, value: u8) {
+            // The cursor registers only affect the displayed cursor.
+            unsafe {
+                self.index.write(register);
+                self.data.write(value);
+            }
+        }
+    }
+
+    /// First scanline of the visible hardware cursor.
+    const CURSOR_START_SCANLINE: u8 = 14;
+    /// Last scanline of the visible hardware cursor.
+    const CURSOR_END_SCANLINE: u8 = 15;
+
     impl Cursor {
-        /// Moves cursor by one character.
-        fn update_position(&self) {
-            use crate::port::Port;
+        /// Moves hardware cursor to the cursor position on a screen `columns` wide.
+        /// Hidden hardware cursor is not moved.
+        fn update_position(&self, columns: usize) {
+            if !self.visible {
+                return;
+            }
 
-            let a = Port::new(0x3d4);
-            let b = Port::new(0x3d5);
-            let pos = self.to_array_index();
+            let pos = self.to_array_index(columns);
+            CRTC.write_cursor_register(CURSOR_LOW, (pos & 0xff) as u8);
+            CRTC.write_cursor_register(CURSOR_HIGH, ( (pos >> 8) & 0xff) as u8);
+        }
 
-            a.write_byte(0x0f);
-            b.write_byte((pos & 0xff) as u8);
-            a.write_byte(0x0e);
-            b.write_byte( ( (pos >> 8) & 0xff) as u8);
+        /// Shows or hides the hardware cursor by programming the cursor start
+        /// (`0x0a`) and cursor end (`0x0b`) registers.
+        fn set_visible(&mut self, visible: bool, columns: usize) {
+            self.visible = visible;
+            if visible {
+                CRTC.write_cursor_register(CURSOR_START, CURSOR_START_SCANLINE);
+                CRTC.write_cursor_register(CURSOR_END, CURSOR_END_SCANLINE);
+                // The position was not tracked while hidden.
+                self.update_position(columns);
+            } else {
+                // Bit 5 of the cursor start register disables the cursor.
+                CRTC.write_cursor_register(CURSOR_START, 1 << 5);
+            }
         }
 
-        /// Returns cursor position as array offset.
-        fn to_array_index(&self) -> usize{
-            self.y * COLUMNS + self.x
+        /// Returns cursor position as array offset on a screen `columns` wide.
+        fn to_array_index(&self, columns: usize) -> usize{
+            self.y * columns + self.x
         }
     }
 
+    /// Byte starting an ANSI escape sequence.
+    const ESC: u8 = 0x1b;
+    /// Maximum number of parameters of an escape sequence, later ones are ignored.
+    const MAX_ESCAPE_PARAMS: usize = 4;
+
+    /// Progress of parsing an ANSI escape sequence in [`Monitor::write_str`].
+    #[derive(Debug, Clone, Copy)]
+    enum Escape {
+        /// Not inside an escape sequence.
+        None,
+        /// After `ESC`.
+        Start,
+        /// After `ESC [`, with the parameters read so far.
+        /// `count` is the index of the parameter currently read.
+        Csi { params: [u16; MAX_ESCAPE_PARAMS], count: usize },
+    }
+
     /// Structure used to write to vga display.
     #[derive(Debug)]
     pub struct Monitor {
         cursor: Cursor,
-        /// The buffer of the vga device. All writes and reads should be `volatile`.
-        buffer: &'static mut [[u16; COLUMNS as usize]; ROWS as usize],
+        /// The buffer of the vga device, `dimensions.cells()` cells row by row.
+        /// All writes and reads are `volatile`.
+        buffer: &'static mut [Volatile<u16>],
+        dimensions: BufferDimensions,
+        /// Target of all writes while double buffered, laid out like `buffer`.
+        /// Empty while not double buffered.
+        back_buffer: Vec<u16>,
+        /// Content of `buffer` as of the last [`flush`](Monitor::flush).
+        front_buffer: Vec<u16>,
+        /// Rows of `back_buffer` written since the last flush.
+        dirty_rows: Vec<bool>,
+        double_buffered: bool,
+        /// Whether writes past the scroll region scroll it, otherwise they fail.
+        scrolling: bool,
+        /// Escape sequence being parsed, kept across `write_str` calls.
+        escape: Escape,
+        /// First row of the region shifted by [`scroll`](Monitor::scroll).
+        scroll_top: usize,
+        /// Last row of the region shifted by [`scroll`](Monitor::scroll).
+        scroll_bottom: usize,
         background_color: Color,
         foreground_color: Color,
     }
 
+    /// Outcome of [`Monitor::write_str`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Written {
+        /// Number of bytes consumed, which is all of them.
+        pub bytes: usize,
+        /// Whether the display scrolled while writing.
+        pub scrolled: bool,
+    }
+
+    /// Error of the fallible writes of [`Monitor`], such as [`Monitor::try_write_str`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum VgaError {
+        /// The write would have scrolled a screen with scrolling disabled.
+        OutOfBounds,
+        /// Writes to the back buffer are not shown yet.
+        NotFlushed,
+    }
+
+    /// Screen content and cursor position saved by [`Monitor::snapshot`].
+    #[derive(Clone)]
+    pub struct Snapshot {
+        dimensions: BufferDimensions,
+        /// Cells row by row.
+        cells: Vec<u16>,
+        cursor: (usize, usize),
+    }
+
+    /// Content of a rectangle of the screen saved by [`Monitor::snapshot_region`].
+    #[derive(Debug, Clone)]
+    pub struct RegionSnapshot {
+        x: usize,
+        y: usize,
+        /// Width of the saved part of the rectangle, which lies on the screen.
+        width: usize,
+        /// Cells row by row.
+        cells: Vec<u16>,
+    }
+
+    use alloc::vec::Vec;
     use core::fmt;
     impl fmt::Write for Monitor {
         fn write_str(&mut self, s: &str) -> fmt::Result {
-            self.write_str(s);
-            Ok(())
+            self.try_write_str(s).map_err(|_| fmt::Error)
+        }
+    }
+
+    impl Console for Monitor {
+        fn write_byte(&mut self, byte: u8) {
+            // Consoles can't report errors, the rest of the output is dropped.
+            let _ = self.try_write_byte(byte);
+        }
+
+        fn write_str(&mut self, s: &str) {
+            let _ = self.try_write_str(s);
         }
     }
 
     impl Monitor {
+        /// Creates monitor writing to the text buffer at `base` of size `dimensions`,
+        /// for example an in-memory one in tests. White on black, with the whole
+        /// screen scrolling.
+        ///
+        /// The hardware cursor is treated as hidden, so that a monitor not writing
+        /// to the display doesn't move it.
+        ///
+        /// # Safety
+        /// `base` must point to `dimensions.cells()` cells, valid forever and not
+        /// written through anything else.
+        pub unsafe fn new(base: *mut u16, dimensions: BufferDimensions) -> Monitor {
+            Monitor {
+                cursor: Cursor { x: 0, y: 0, visible: false },
+                // `Volatile` is transparent.
+                buffer: core::slice::from_raw_parts_mut(base as *mut Volatile<u16>, dimensions.cells()),
+                dimensions,
+                back_buffer: Vec::new(),
+                front_buffer: Vec::new(),
+                dirty_rows: Vec::new(),
+                double_buffered: false,
+                scrolling: true,
+                escape: Escape::None,
+                scroll_top: 0,
+                scroll_bottom: dimensions.rows - 1,
+                background_color: Color::Black,
+                foreground_color: Color::White,
+            }
+        }
+
+        /// Returns size of the screen.
+        #[allow(dead_code)]
+        pub fn dimensions(&self) -> BufferDimensions {
+            self.dimensions
+        }
+
+        /// Makes the screen `cols` x `rows` characters, for example after switching
+        /// the display to 80x50 text mode. The buffer keeps its base address, the
+        /// cursor is clamped to the new screen and the scroll region is reset to
+        /// the whole screen. The content isn't cleared.
+        ///
+        /// # Safety
+        /// The buffer must hold `cols * rows` cells, see [`new`](Self::new).
+        ///
+        /// # Panics
+        /// If `cols` or `rows` is zero.
+        #[allow(dead_code)]
+        pub unsafe fn set_dimensions(&mut self, cols: usize, rows: usize) {
+            assert!(cols > 0 && rows > 0, "invalid dimensions {}x{}", cols, rows);
+
+            let double_buffered = self.double_buffered;
+            self.set_double_buffered(false);
+
+            self.dimensions = BufferDimensions { cols, rows };
+            let base = self.buffer.as_mut_ptr();
+            self.buffer = core::slice::from_raw_parts_mut(base, self.dimensions.cells());
+            self.scroll_top = 0;
+            self.scroll_bottom = rows - 1;
+            self.set_cursor_position(self.cursor.x, self.cursor.y);
+
+            self.set_double_buffered(double_buffered);
+        }
+
         /// Sets backgound color for writes to vga.
+        #[allow(dead_code)]
         #[inline(always)]
         pub fn set_background_color(&mut self, color: &Color) {
-            self.background_color = color.clone();
+            self.background_color = *color;
         }
 
         /// Sets foreground color for writes to vga.
+        #[allow(dead_code)]
         #[inline(always)]
         pub fn set_foreground_color(&mut self, color: &Color) {
-            self.foreground_color = color.clone();
+            self.foreground_color = *color;
+        }
+
+        /// Moves the cursor to column `x` and row `y`.
+        /// Coordinates outside of the screen are clamped to the nearest edge.
+        #[allow(dead_code)]
+        pub fn set_cursor_position(&mut self, x: usize, y: usize) {
+            self.cursor.x = core::cmp::min(x, self.dimensions.cols - 1);
+            self.cursor.y = core::cmp::min(y, self.dimensions.rows - 1);
+            self.cursor.update_position(self.dimensions.cols);
+        }
+
+        /// Returns current cursor position as `(column, row)`.
+        #[allow(dead_code)]
+        pub fn cursor_position(&self) -> (usize, usize) {
+            (self.cursor.x, self.cursor.y)
+        }
+
+        /// Hides the blinking hardware cursor.
+        #[allow(dead_code)]
+        pub fn hide_cursor(&mut self) {
+            self.cursor.set_visible(false, self.dimensions.cols);
+        }
+
+        /// Shows the blinking hardware cursor at the cursor position.
+        #[allow(dead_code)]
+        pub fn show_cursor(&mut self) {
+            self.cursor.set_visible(true, self.dimensions.cols);
+        }
+
+        /// Returns character and attribute at column `x` and row `y`
+        /// from the buffer writes currently go to.
+        ///
+        /// All reads of the screen go through here, `x` and `y` must lie on it.
+        fn cell(&self, x: usize, y: usize) -> u16 {
+            debug_assert!(x < self.dimensions.cols && y < self.dimensions.rows,
+                          "cell ({}, {}) outside of the screen", x, y);
+            let index = self.dimensions.index(x, y);
+            if self.double_buffered {
+                self.back_buffer[index]
+            } else {
+                self.buffer[index].read()
+            }
+        }
+
+        /// Writes character and attribute `value` at column `x` and row `y`,
+        /// directly to the display or to the back buffer when double buffered.
+        ///
+        /// All writes to the screen go through here, `x` and `y` must lie on it.
+        fn set_cell(&mut self, x: usize, y: usize, value: u16) {
+            debug_assert!(x < self.dimensions.cols && y < self.dimensions.rows,
+                          "cell ({}, {}) outside of the screen", x, y);
+            let index = self.dimensions.index(x, y);
+            if self.double_buffered {
+                self.back_buffer[index] = value;
+                self.dirty_rows[y] = true;
+            } else {
+                self.buffer[index].write(value);
+            }
+        }
+
+        /// Makes writes go to an off-screen back buffer shown by [`flush`](Self::flush)
+        /// if `enabled`, otherwise directly to the display. Avoids flicker on full redraws.
+        ///
+        /// Disabling double buffering flushes pending writes.
+        #[allow(dead_code)]
+        pub fn set_double_buffered(&mut self, enabled: bool) {
+            if enabled == self.double_buffered {
+                return;
+            }
+
+            if enabled {
+                self.back_buffer = self.buffer.iter().map(Volatile::read).collect();
+                self.front_buffer = self.back_buffer.clone();
+                self.dirty_rows = alloc::vec![false; self.dimensions.rows];
+            } else {
+                self.flush();
+                self.back_buffer = Vec::new();
+                self.front_buffer = Vec::new();
+                self.dirty_rows = Vec::new();
+            }
+            self.double_buffered = enabled;
+        }
+
+        /// Copies cells of the back buffer that changed since the last flush to
+        /// the display. Does nothing when not double buffered.
+        #[allow(dead_code)]
+        pub fn flush(&mut self) {
+            if !self.double_buffered {
+                return;
+            }
+
+            for y in 0..self.dimensions.rows {
+                if !self.dirty_rows[y] {
+                    continue;
+                }
+                for x in 0..self.dimensions.cols {
+                    let index = self.dimensions.index(x, y);
+                    if self.back_buffer[index] != self.front_buffer[index] {
+                        self.buffer[index].write(self.back_buffer[index]);
+                        self.front_buffer[index] = self.back_buffer[index];
+                    }
+                }
+                self.dirty_rows[y] = false;
+            }
+        }
+
+        /// Returns [`VgaError::NotFlushed`] if writes to the back buffer
+        /// happened since the last [`flush`](Self::flush).
+        #[allow(dead_code)]
+        pub fn check_flushed(&self) -> Result<(), VgaError> {
+            if self.dirty_rows.iter().any(|&dirty| dirty) {
+                Err(VgaError::NotFlushed)
+            } else {
+                Ok(())
+            }
+        }
+
+        /// Returns the character byte at column `x` and row `y`.
+        #[allow(dead_code)]
+        pub fn read_char_at(&self, x: usize, y: usize) -> u8 {
+            self.cell(x, y) as u8
+        }
+
+        /// Returns copy of the whole screen and the cursor position, for example
+        /// to put back with [`restore`](Self::restore) what an overlay covered.
+        #[allow(dead_code)]
+        pub fn snapshot(&self) -> Snapshot {
+            let mut cells = Vec::with_capacity(self.dimensions.cells());
+            for y in 0..self.dimensions.rows {
+                for x in 0..self.dimensions.cols {
+                    cells.push(self.cell(x, y));
+                }
+            }
+            Snapshot { dimensions: self.dimensions, cells, cursor: self.cursor_position() }
+        }
+
+        /// Writes back screen content and cursor position of `snapshot`. If the
+        /// screen was resized since, only the part on both screens is written back.
+        #[allow(dead_code)]
+        pub fn restore(&mut self, snapshot: &Snapshot) {
+            let cols = core::cmp::min(snapshot.dimensions.cols, self.dimensions.cols);
+            let rows = core::cmp::min(snapshot.dimensions.rows, self.dimensions.rows);
+            for y in 0..rows {
+                for x in 0..cols {
+                    self.set_cell(x, y, snapshot.cells[snapshot.dimensions.index(x, y)]);
+                }
+            }
+            let (x, y) = snapshot.cursor;
+            self.set_cursor_position(x, y);
+        }
+
+        /// Returns copy of the `w` x `h` rectangle with top left corner at `(x, y)`,
+        /// which only takes as much memory as the rectangle. The part of the
+        /// rectangle outside of the screen is ignored and the cursor isn't saved.
+        #[allow(dead_code)]
+        pub fn snapshot_region(&self, x: usize, y: usize, w: usize, h: usize) -> RegionSnapshot {
+            let end_x = core::cmp::min(x.saturating_add(w), self.dimensions.cols);
+            let end_y = core::cmp::min(y.saturating_add(h), self.dimensions.rows);
+
+            let mut cells = Vec::new();
+            for row in y..end_y {
+                for column in x..end_x {
+                    cells.push(self.cell(column, row));
+                }
+            }
+            RegionSnapshot { x, y, width: end_x.saturating_sub(x), cells }
+        }
+
+        /// Writes back the rectangle saved in `snapshot`, leaving the cursor in place.
+        #[allow(dead_code)]
+        pub fn restore_region(&mut self, snapshot: &RegionSnapshot) {
+            if snapshot.width == 0 {
+                return;
+            }
+            for (i, row) in snapshot.cells.chunks(snapshot.width).enumerate() {
+                for (j, &cell) in row.iter().enumerate() {
+                    self.set_cell(snapshot.x + j, snapshot.y + i, cell);
+                }
+            }
+        }
+
+        /// Returns the attribute byte (background and foreground color)
+        /// of the character at column `x` and row `y`.
+        pub fn read_attr_at(&self, x: usize, y: usize) -> u8 {
+            (self.cell(x, y) >> 8) as u8
+        }
+
+        /// Swaps the foreground and background color of the character at
+        /// column `x` and row `y`, leaving the glyph untouched.
+        /// Coordinates outside of the screen are ignored.
+        pub fn invert_cell(&mut self, x: usize, y: usize) {
+            if x >= self.dimensions.cols || y >= self.dimensions.rows {
+                return;
+            }
+
+            let attribute = self.read_attr_at(x, y);
+            let inverted = attribute.rotate_left(4);
+            let glyph = self.cell(x, y) & 0x00ff;
+            self.set_cell(x, y, glyph | ( (inverted as u16) << 8));
+        }
+
+        /// Inverts every cell of the `w` x `h` rectangle with top left corner
+        /// at `(x, y)`. The part of the rectangle outside of the screen is ignored.
+        #[allow(dead_code)]
+        pub fn invert_region(&mut self, x: usize, y: usize, w: usize, h: usize) {
+            let end_x = core::cmp::min(x.saturating_add(w), self.dimensions.cols);
+            let end_y = core::cmp::min(y.saturating_add(h), self.dimensions.rows);
+
+            for row in y..end_y {
+                for column in x..end_x {
+                    self.invert_cell(column, row);
+                }
+            }
+        }
+
+        /// Fills the `w` x `h` rectangle with top left corner at `(x, y)` with
+        /// `ch` in foreground color `fg` and background color `bg`. The part of
+        /// the rectangle outside of the screen is ignored and the cursor doesn't move.
+        #[allow(dead_code)]
+        #[allow(clippy::too_many_arguments)]
+        pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, ch: u8, fg: Color, bg: Color) {
+            let end_x = core::cmp::min(x.saturating_add(w), self.dimensions.cols);
+            let end_y = core::cmp::min(y.saturating_add(h), self.dimensions.rows);
+            let character = vga_char(ch, bg, fg);
+
+            for row in y..end_y {
+                for column in x..end_x {
+                    self.set_cell(column, row, character);
+                }
+            }
+        }
+
+        /// Draws border of the `w` x `h` rectangle with top left corner at `(x, y)`
+        /// using CP437 box drawing characters, leaving its inside untouched.
+        /// Clipped like [`fill_rect`](Self::fill_rect), rectangles thinner than
+        /// 2 cells are not drawn.
+        #[allow(dead_code)]
+        pub fn draw_box(&mut self, x: usize, y: usize, w: usize, h: usize, fg: Color, bg: Color) {
+            const TOP_LEFT: u8 = 0xc9;
+            const TOP_RIGHT: u8 = 0xbb;
+            const BOTTOM_LEFT: u8 = 0xc8;
+            const BOTTOM_RIGHT: u8 = 0xbc;
+            const HORIZONTAL: u8 = 0xcd;
+            const VERTICAL: u8 = 0xba;
+
+            if w < 2 || h < 2 {
+                return;
+            }
+            let (inner_x, inner_y) = (x.saturating_add(1), y.saturating_add(1));
+            let right = x.saturating_add(w - 1);
+            let bottom = y.saturating_add(h - 1);
+
+            self.fill_rect(inner_x, y, w - 2, 1, HORIZONTAL, fg, bg);
+            self.fill_rect(inner_x, bottom, w - 2, 1, HORIZONTAL, fg, bg);
+            self.fill_rect(x, inner_y, 1, h - 2, VERTICAL, fg, bg);
+            self.fill_rect(right, inner_y, 1, h - 2, VERTICAL, fg, bg);
+            self.fill_rect(x, y, 1, 1, TOP_LEFT, fg, bg);
+            self.fill_rect(right, y, 1, 1, TOP_RIGHT, fg, bg);
+            self.fill_rect(x, bottom, 1, 1, BOTTOM_LEFT, fg, bg);
+            self.fill_rect(right, bottom, 1, 1, BOTTOM_RIGHT, fg, bg);
         }
 
-        /// Fills the full screen (`ROWS * COLUMNS`) with blank (`' '`) character
+        /// Fills every cell of the screen with blank (`' '`) character
         /// and sets cursor position to top left corner.
         pub fn clear(&mut self) {
-            let cursor = &mut self.cursor;
-
-            let blank_character = vga_char(' ' as u8,
+            let blank_character = vga_char(b' ',
                                            self.background_color,
                                            self.foreground_color);
             let mut row = 0;
-            while row < ROWS {
+            while row < self.dimensions.rows {
                 let mut column = 0;
-                while column < COLUMNS {
-                    self.buffer[row][column] = blank_character;
+                while column < self.dimensions.cols {
+                    self.set_cell(column, row, blank_character);
                     column += 1;
                 }
 
                 row += 1;
             }
 
-            cursor.x = 0;
-            cursor.y = 0;
+            self.cursor.x = 0;
+            self.cursor.y = 0;
+        }
+
+        /// Fills row `y` with blank (`' '`) characters in the current colors.
+        /// Rows outside of the screen are ignored.
+        #[allow(dead_code)]
+        pub fn clear_row(&mut self, y: usize) {
+            if y >= self.dimensions.rows {
+                return;
+            }
+
+            let blank_character = vga_char(b' ',
+                                           self.background_color,
+                                           self.foreground_color);
+            for column in 0..self.dimensions.cols {
+                self.set_cell(column, y, blank_character);
+            }
+        }
+
+        /// Clears the row the cursor is on and moves the cursor to its beginning.
+        #[allow(dead_code)]
+        pub fn clear_current_line(&mut self) {
+            self.clear_row(self.cursor.y);
+            self.cursor.x = 0;
+            self.cursor.update_position(self.dimensions.cols);
         }
 
         /// Prints `byte` to current position on vga display
@@ -186,18 +907,40 @@ pub mod VGA {
                 b'\n' => {
                     cursor.x = 0;
                     cursor.y += 1;
+                    self.settle_cursor();
                     return;
                 },
                 b'\r' => {
                     cursor.x = 0;
+                    self.settle_cursor();
                     return;
                 },
-                0x08 =>{
-                    cursor.x -= 1;
+                0x08 => {
+                    // Move to the end of the previous row when at the start
+                    // of a line, stay put in the top left corner.
+                    if cursor.x > 0 {
+                        cursor.x -= 1;
+                    } else if cursor.y > 0 {
+                        cursor.x = self.dimensions.cols - 1;
+                        cursor.y -= 1;
+                    }
+
+                    let blank_character = vga_char(b' ',
+                                                   self.background_color,
+                                                   self.foreground_color);
+                    let (x, y) = (cursor.x, cursor.y);
+                    self.set_cell(x, y, blank_character);
+                    self.cursor.update_position(self.dimensions.cols);
                     return;
                 },
                 b'\t' => {
-                    cursor.x = ( (cursor.x % TAB_WIDTH) + 1 ) * TAB_WIDTH;
+                    // Advance to the next multiple of `TAB_WIDTH`.
+                    cursor.x = (cursor.x / TAB_WIDTH + 1) * TAB_WIDTH;
+                    if cursor.x >= self.dimensions.cols {
+                        cursor.x = 0;
+                        cursor.y += 1;
+                    }
+                    self.settle_cursor();
                     return;
                 },
                 _ => {}
@@ -212,55 +955,734 @@ pub mod VGA {
                 //return;
             }
 
-            self.buffer[cursor.y][cursor.x] = character;
+            let (x, y) = (cursor.x, cursor.y);
+            self.set_cell(x, y, character);
 
+            let cursor = &mut self.cursor;
             cursor.x += 1;
-            if cursor.x >= COLUMNS {
+            if cursor.x >= self.dimensions.cols {
                 cursor.x = 0;
                 cursor.y += 1;
             }
-            cursor.update_position();
+            self.settle_cursor();
+        }
+
+        /// Prints `byte` like [`write_byte`](Self::write_byte), but in foreground
+        /// color `fg` and background color `bg`. Colors used by later writes are not changed.
+        #[allow(dead_code)]
+        pub fn write_colored(&mut self, byte: u8, fg: Color, bg: Color) {
+            let (saved_fg, saved_bg) = (self.foreground_color, self.background_color);
 
-            if cursor.y >= ROWS {
+            self.foreground_color = fg;
+            self.background_color = bg;
+            self.write_byte(byte);
+            self.foreground_color = saved_fg;
+            self.background_color = saved_bg;
+        }
+
+        /// Scrolls the scroll region if the cursor moved past its last row, leaving
+        /// the cursor on that row, and moves the hardware cursor to it.
+        /// Below the scroll region the cursor stops at the last row without scrolling.
+        fn settle_cursor(&mut self) {
+            if self.cursor.y == self.scroll_bottom + 1 {
                 self.scroll();
+                self.cursor.y = self.scroll_bottom;
+            } else if self.cursor.y >= self.dimensions.rows {
+                self.cursor.y = self.dimensions.rows - 1;
             }
+            self.cursor.update_position(self.dimensions.cols);
         }
 
-        pub fn write_str(&mut self, string: &str) {
-            string.bytes().for_each(|byte| self.write_byte(byte));
-            /*
-            let mut i: usize = 0;
-            while i < string.len() {
-                Self::write_byte(string[i]);
+        /// Limits scrolling to rows `top` up to and including `bottom`, rows outside
+        /// of them stay in place. The whole screen scrolls by default.
+        ///
+        /// # Panics
+        /// If not `top < bottom < dimensions().rows`.
+        #[allow(dead_code)]
+        pub fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+            assert!(top < bottom && bottom < self.dimensions.rows, "invalid scroll region {}..={}", top, bottom);
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+        }
 
-                i += 1;
+        /// Prints `string` with [`write_byte`](Self::write_byte), interpreting ANSI
+        /// SGR sequences such as `"\x1b[31m"` to change colors. Other escape
+        /// sequences are not printed.
+        #[allow(dead_code)]
+        pub fn write_str(&mut self, string: &str) -> Written {
+            let mut scrolled = false;
+            for byte in string.bytes() {
+                scrolled |= self.would_scroll(byte);
+                self.write_escaped(byte);
+            }
+            Written { bytes: string.len(), scrolled }
+        }
+
+        /// Makes writes through [`try_write_str`](Self::try_write_str), and so `write!`,
+        /// scroll past the last row of the scroll region if `enabled`, otherwise fail
+        /// with [`VgaError::OutOfBounds`] there. Enabled by default.
+        #[allow(dead_code)]
+        pub fn set_scrolling(&mut self, enabled: bool) {
+            self.scrolling = enabled;
+        }
+
+        /// Prints `byte` like [`write_str`](Self::write_str), unless it would scroll
+        /// with scrolling disabled by [`set_scrolling`](Self::set_scrolling).
+        pub fn try_write_byte(&mut self, byte: u8) -> Result<(), VgaError> {
+            if !self.scrolling && self.would_scroll(byte) {
+                return Err(VgaError::OutOfBounds);
+            }
+            self.write_escaped(byte);
+            Ok(())
+        }
+
+        /// Prints `string` with [`try_write_byte`](Self::try_write_byte), stopping
+        /// at the first byte that fails.
+        ///
+        /// Printing to the last column of the last row of the scroll region
+        /// would scroll, so with scrolling disabled that cell stays unchanged.
+        pub fn try_write_str(&mut self, string: &str) -> Result<(), VgaError> {
+            string.bytes().try_for_each(|byte| self.try_write_byte(byte))
+        }
+
+        /// Prints `string` like [`write_str`](Self::write_str), but stops before
+        /// the first byte that would scroll the display. Returns number of bytes written.
+        ///
+        /// Printing to the last column of the last row of the scroll region
+        /// would scroll, so that cell stays unchanged.
+        #[allow(dead_code)]
+        pub fn write_str_no_scroll(&mut self, string: &str) -> usize {
+            for (i, byte) in string.bytes().enumerate() {
+                if self.would_scroll(byte) {
+                    return i;
+                }
+                self.write_escaped(byte);
+            }
+            string.len()
+        }
+
+        /// Prints `string` as a field `max_cols` columns wide starting at the cursor,
+        /// without wrapping or scrolling. Control characters are skipped, a shorter
+        /// string is padded with blanks and a longer one is cut off, its last visible
+        /// character replaced by [`TRUNCATION_MARK`]. The field ends at the last column.
+        ///
+        /// Leaves the cursor just after the field, or in the last column if the
+        /// field ends there.
+        #[allow(dead_code)]
+        pub fn write_str_clamped(&mut self, string: &str, max_cols: usize) {
+            let (x, y) = (self.cursor.x, self.cursor.y);
+            let width = core::cmp::min(max_cols, self.dimensions.cols - x);
+
+            let mut visible = string.bytes().filter(|&byte| byte >= 0x80 || is_printable(byte));
+            for column in x..x + width {
+                let byte = match visible.next() {
+                    Some(_) if column == x + width - 1 && visible.clone().next().is_some() => TRUNCATION_MARK,
+                    Some(byte) if is_printable(byte) => byte,
+                    // Like `write_byte`, '■' for bytes outside of ASCII.
+                    Some(_) => 0xfe,
+                    None => b' ',
+                };
+                self.set_cell(column, y, vga_char(byte, self.background_color, self.foreground_color));
+            }
+
+            self.cursor.x = core::cmp::min(x + width, self.dimensions.cols - 1);
+            self.cursor.update_position(self.dimensions.cols);
+        }
+
+        /// Returns whether passing `byte` to [`write_escaped`](Self::write_escaped)
+        /// scrolls the display.
+        fn would_scroll(&self, byte: u8) -> bool {
+            if !matches!(self.escape, Escape::None) || byte == ESC || self.cursor.y != self.scroll_bottom {
+                return false;
+            }
+
+            match byte {
+                b'\n' => true,
+                b'\r' | 0x08 => false,
+                b'\t' => (self.cursor.x / TAB_WIDTH + 1) * TAB_WIDTH >= self.dimensions.cols,
+                _ => self.cursor.x == self.dimensions.cols - 1,
+            }
+        }
+
+        /// Feeds `byte` to the escape sequence parser, printing it if it is
+        /// not part of a sequence.
+        fn write_escaped(&mut self, byte: u8) {
+            self.escape = match (self.escape, byte) {
+                (Escape::None, ESC) => Escape::Start,
+                (Escape::None, _) => {
+                    self.write_byte(byte);
+                    Escape::None
+                },
+                (Escape::Start, b'[') => Escape::Csi { params: [0; MAX_ESCAPE_PARAMS], count: 0 },
+                // Two byte sequences, nothing to do for them.
+                (Escape::Start, _) => Escape::None,
+                (Escape::Csi { mut params, count }, b'0'..=b'9') => {
+                    if count < MAX_ESCAPE_PARAMS {
+                        params[count] = params[count].saturating_mul(10)
+                            .saturating_add((byte - b'0') as u16);
+                    }
+                    Escape::Csi { params, count }
+                },
+                (Escape::Csi { params, count }, b';') => Escape::Csi { params, count: count + 1 },
+                (Escape::Csi { params, count }, b'm') => {
+                    let count = core::cmp::min(count + 1, MAX_ESCAPE_PARAMS);
+                    params[..count].iter().for_each(|&param| self.select_graphic_rendition(param));
+                    Escape::None
+                },
+                // Other parameter and intermediate bytes.
+                (Escape::Csi { .. }, 0x20..=0x3f) => self.escape,
+                // Final bytes of unsupported sequences and bytes not allowed
+                // in a sequence end it.
+                (Escape::Csi { .. }, _) => Escape::None,
+            };
+        }
+
+        /// Applies SGR parameter `param`: 0 resets colors, 30-37 and 40-47 set
+        /// the foreground and background color. Others are ignored.
+        fn select_graphic_rendition(&mut self, param: u16) {
+            // ANSI color order, which differs from the VGA one.
+            const COLORS: [Color; 8] = [
+                Color::Black, Color::Red, Color::Green, Color::Brown,
+                Color::Blue, Color::Purple, Color::Cyan, Color::Grey,
+            ];
+
+            match param {
+                0 => {
+                    self.foreground_color = Color::White;
+                    self.background_color = Color::Black;
+                },
+                30..=37 => self.foreground_color = COLORS[(param - 30) as usize],
+                40..=47 => self.background_color = COLORS[(param - 40) as usize],
+                _ => {},
+            }
+        }
+
+        /// Prints `value` as `0x` followed by uppercase hexadecimal digits without
+        /// leading zeros. Unlike `write!` it needs no formatting machinery.
+        #[allow(dead_code)]
+        pub fn write_hex(&mut self, value: u64) {
+            let mut digits = [0u8; 16];
+            let start = Self::digits(value, 16, &mut digits);
+
+            self.write_str("0x");
+            digits[start..].iter().for_each(|&digit| self.write_byte(digit));
+        }
+
+        /// Prints `value` in decimal, see [`write_hex`](Self::write_hex).
+        #[allow(dead_code)]
+        pub fn write_dec(&mut self, value: u64) {
+            let mut digits = [0u8; 20];
+            let start = Self::digits(value, 10, &mut digits);
+
+            digits[start..].iter().for_each(|&digit| self.write_byte(digit));
+        }
+
+        /// Writes digits of `value` in `base` to the end of `digits`
+        /// and returns index of the first one.
+        fn digits(mut value: u64, base: u64, digits: &mut [u8]) -> usize {
+            let mut start = digits.len();
+            loop {
+                start -= 1;
+                digits[start] = b"0123456789ABCDEF"[(value % base) as usize];
+                value /= base;
+                if value == 0 {
+                    return start;
+                }
             }
-            */
         }
 
-        /// Move all rows one row up. First row gets lost
-        /// and last row gets filled with spaces.
+        /// Move all rows of the scroll region one row up. Its first row gets lost
+        /// and its last row gets filled with spaces.
         fn scroll(&mut self) {
-            let mut i = 0;
-            while i < ROWS - 1 {
+            let mut i = self.scroll_top;
+            while i < self.scroll_bottom {
                 let mut column = 0;
-                while column < COLUMNS {
+                while column < self.dimensions.cols {
                     // move every row one row up
-                    // Since vga display is one array, COLUMNS == one row
-                    self.buffer[i][column] = self.buffer[i + 1][column];
+                    let character = self.cell(column, i + 1);
+                    self.set_cell(column, i, character);
                     column += 1;
                 }
                 i += 1;
             }
 
-            let blank_character: u16 = vga_char(' ' as u8,
+            let blank_character: u16 = vga_char(b' ',
                                                 self.background_color,
                                                 self.foreground_color);
             i = 0;
-            while i < COLUMNS {
-                self.buffer[ROWS - 1][i] = blank_character;
+            while i < self.dimensions.cols {
+                self.set_cell(i, self.scroll_bottom, blank_character);
                 i += 1;
             }
         }
     }
+
+    #[test_case]
+    fn bright_and_dim_colors() {
+        use Color::*;
+        let pairs = [(Black, DarkGrey), (Blue, LightBlue), (Green, LightGreen), (Cyan, LightCyan),
+                     (Red, LightRed), (Purple, LightPurple), (Brown, Yellow), (Grey, White)];
+        for (dim, bright) in pairs {
+            assert_eq!(dim.bright(), bright);
+            assert_eq!(bright.bright(), bright);
+            assert_eq!(bright.dim(), dim);
+            assert_eq!(dim.dim(), dim);
+        }
+    }
+
+    #[test_case]
+    fn invert_cell_twice() {
+        {
+            let mut monitor = BUFFER.lock();
+            monitor.buffer[0].write(vga_char(b'x', Color::Blue, Color::Yellow));
+            let original = monitor.read_attr_at(0, 0);
+
+            monitor.invert_cell(0, 0);
+            assert_eq!(monitor.read_attr_at(0, 0), (vga_char(b'x', Color::Yellow, Color::Blue) >> 8) as u8);
+            monitor.invert_cell(0, 0);
+            assert_eq!(monitor.read_attr_at(0, 0), original);
+            assert_eq!(monitor.buffer[0].read() & 0x00ff, b'x' as u16);
+        }
+    }
+
+    #[test_case]
+    fn volatile_write_survives() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = (monitor.cursor.x, monitor.cursor.y);
+            monitor.write_byte(b'V');
+            assert_eq!(monitor.buffer[y * COLUMNS + x].read(), vga_char(b'V', monitor.background_color, monitor.foreground_color));
+        }
+    }
+
+    #[test_case]
+    fn backspace_at_line_start() {
+        {
+            let mut monitor = BUFFER.lock();
+            let saved = (monitor.cursor.x, monitor.cursor.y);
+
+            monitor.cursor.x = 0;
+            monitor.cursor.y = 5;
+            monitor.write_byte(0x08);
+            assert_eq!((monitor.cursor.x, monitor.cursor.y), (COLUMNS - 1, 4));
+
+            monitor.cursor.x = 0;
+            monitor.cursor.y = 0;
+            monitor.write_byte(0x08);
+            assert_eq!((monitor.cursor.x, monitor.cursor.y), (0, 0));
+
+            monitor.cursor.x = saved.0;
+            monitor.cursor.y = saved.1;
+        }
+    }
+
+    #[test_case]
+    fn tab_advances_to_next_stop() {
+        {
+            let mut monitor = BUFFER.lock();
+            let saved = (monitor.cursor.x, monitor.cursor.y);
+
+            monitor.cursor.x = 0;
+            monitor.write_str("a\tb");
+            assert_eq!(monitor.buffer[(saved.1) * COLUMNS + TAB_WIDTH].read() & 0x00ff, b'b' as u16);
+
+            monitor.cursor.x = 3;
+            monitor.write_byte(b'\t');
+            assert_eq!(monitor.cursor.x, TAB_WIDTH);
+
+            monitor.cursor.x = saved.0;
+            monitor.cursor.y = saved.1;
+        }
+    }
+
+    #[test_case]
+    fn newline_scrolls() {
+        {
+            let mut monitor = BUFFER.lock();
+            for _ in 0..30 {
+                monitor.write_str("line\n");
+            }
+            assert_eq!((monitor.cursor.x, monitor.cursor.y), (0, ROWS - 1));
+        }
+    }
+
+    #[test_case]
+    fn scroll_region_keeps_outer_rows() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+            let marker = vga_char(b'#', Color::Black, Color::White);
+            let outer = [0, 1, 23, 24];
+            for &row in outer.iter() {
+                monitor.buffer[row * COLUMNS].write(marker);
+            }
+
+            monitor.set_scroll_region(2, 22);
+            monitor.set_cursor_position(0, 2);
+            for _ in 0..30 {
+                monitor.write_str("line\n");
+            }
+            assert_eq!(monitor.cursor_position(), (0, 22));
+            assert_eq!(monitor.buffer[21 * COLUMNS].read() & 0x00ff, b'l' as u16);
+            for &row in outer.iter() {
+                assert_eq!(monitor.buffer[row * COLUMNS].read(), marker);
+            }
+
+            monitor.set_scroll_region(0, ROWS - 1);
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn draw_box_corners_and_clipping() {
+        {
+            let mut monitor = BUFFER.lock();
+            let cursor = monitor.cursor_position();
+            let glyph = |monitor: &Monitor, x: usize, y: usize| (monitor.buffer[y * COLUMNS + x].read() & 0x00ff) as u8;
+
+            monitor.fill_rect(10, 5, 6, 4, b'.', Color::Yellow, Color::Blue);
+            monitor.draw_box(10, 5, 6, 4, Color::White, Color::Blue);
+            assert_eq!(glyph(&monitor, 10, 5), 0xc9);
+            assert_eq!(glyph(&monitor, 15, 5), 0xbb);
+            assert_eq!(glyph(&monitor, 10, 8), 0xc8);
+            assert_eq!(glyph(&monitor, 15, 8), 0xbc);
+            assert_eq!(glyph(&monitor, 12, 5), 0xcd);
+            assert_eq!(glyph(&monitor, 10, 6), 0xba);
+            assert_eq!(monitor.buffer[6 * COLUMNS + 11].read(), vga_char(b'.', Color::Blue, Color::Yellow));
+
+            monitor.fill_rect(COLUMNS - 2, ROWS - 2, 10, 10, b'#', Color::White, Color::Black);
+            monitor.draw_box(COLUMNS - 3, ROWS - 3, usize::MAX, 10, Color::White, Color::Black);
+            monitor.draw_box(usize::MAX, usize::MAX, 4, 4, Color::White, Color::Black);
+            assert_eq!(glyph(&monitor, COLUMNS - 3, ROWS - 3), 0xc9);
+            assert_eq!(glyph(&monitor, COLUMNS - 1, ROWS - 1), b'#');
+            assert_eq!(monitor.cursor_position(), cursor);
+        }
+    }
+
+    #[test_case]
+    fn snapshots_restore_screen() {
+        {
+            let mut monitor = BUFFER.lock();
+            let cursor = monitor.cursor_position();
+            monitor.fill_rect(0, 0, COLUMNS, ROWS, b'a', Color::White, Color::Black);
+            monitor.fill_rect(4, 2, 3, 2, b'b', Color::Red, Color::Blue);
+            monitor.set_cursor_position(7, 8);
+            let original = monitor.snapshot();
+
+            monitor.draw_box(0, 0, COLUMNS, ROWS, Color::Yellow, Color::Purple);
+            monitor.set_cursor_position(1, 1);
+            monitor.restore(&original);
+            assert_eq!(monitor.cursor_position(), (7, 8));
+            assert_eq!(monitor.buffer[0].read(), vga_char(b'a', Color::Black, Color::White));
+            assert_eq!(monitor.buffer[3 * COLUMNS + 6].read(), vga_char(b'b', Color::Blue, Color::Red));
+
+            // Only the part on the screen is saved.
+            let region = monitor.snapshot_region(3, 1, 5, 4);
+            let corner = monitor.snapshot_region(COLUMNS - 2, ROWS - 1, 10, 10);
+            assert_eq!((region.cells.len(), corner.cells.len()), (20, 2));
+            monitor.fill_rect(0, 0, COLUMNS, ROWS, b'x', Color::White, Color::Black);
+            monitor.restore_region(&region);
+            monitor.restore_region(&corner);
+            assert_eq!(monitor.buffer[3 * COLUMNS + 6].read(), vga_char(b'b', Color::Blue, Color::Red));
+            assert_eq!(monitor.read_char_at(3, 1), b'a');
+            assert_eq!(monitor.read_char_at(8, 1), b'x');
+            assert_eq!(monitor.read_char_at(COLUMNS - 1, ROWS - 1), b'a');
+            assert_eq!(monitor.cursor_position(), (7, 8));
+
+            monitor.restore(&original);
+            assert!(original.cells.iter().zip(monitor.buffer.iter()).all(|(&cell, shown)| shown.read() == cell));
+            monitor.set_cursor_position(cursor.0, cursor.1);
+        }
+    }
+
+    #[test_case]
+    fn write_str_reports_scrolling() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+
+            monitor.set_cursor_position(0, ROWS - 2);
+            assert_eq!(monitor.write_str("a\n"), Written { bytes: 2, scrolled: false });
+            assert_eq!(monitor.write_str("b\nc"), Written { bytes: 3, scrolled: true });
+            assert_eq!(monitor.cursor_position(), (1, ROWS - 1));
+
+            // Stops before the newline, escape sequences never scroll.
+            assert_eq!(monitor.write_str_no_scroll("d\x1b[0m\ne"), 5);
+            assert_eq!(monitor.cursor_position(), (2, ROWS - 1));
+            monitor.set_cursor_position(COLUMNS - 2, ROWS - 1);
+            assert_eq!(monitor.write_str_no_scroll("fgh"), 1);
+            assert_eq!(monitor.cursor_position(), (COLUMNS - 1, ROWS - 1));
+            assert_eq!(monitor.read_char_at(COLUMNS - 2, ROWS - 1), b'f');
+            assert_eq!(monitor.read_char_at(0, ROWS - 2), b'b');
+
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn clamped_strings_fill_their_field() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+            let row = |monitor: &Monitor, start: usize, len: usize| {
+                let mut bytes = [0u8; 8];
+                for (i, byte) in bytes[..len].iter_mut().enumerate() {
+                    *byte = monitor.read_char_at(start + i, ROWS - 1);
+                }
+                bytes
+            };
+
+            monitor.set_cursor_position(2, ROWS - 1);
+            monitor.write_str_clamped("abcdefgh", 5);
+            assert_eq!(&row(&monitor, 2, 5)[..5], &[b'a', b'b', b'c', b'd', TRUNCATION_MARK]);
+            assert_eq!(monitor.cursor_position(), (7, ROWS - 1));
+
+            // Control characters take no column.
+            monitor.write_str_clamped("h\ni\n", 4);
+            assert_eq!(&row(&monitor, 7, 4)[..4], b"hi  ");
+            assert_eq!(monitor.cursor_position(), (11, ROWS - 1));
+            monitor.write_str_clamped("fits", 4);
+            assert_eq!(&row(&monitor, 11, 4)[..4], b"fits");
+
+            // The field ends at the last column without wrapping.
+            monitor.set_cursor_position(COLUMNS - 3, ROWS - 1);
+            monitor.write_str_clamped("xyz!", 10);
+            assert_eq!(&row(&monitor, COLUMNS - 3, 3)[..3], &[b'x', b'y', TRUNCATION_MARK]);
+            assert_eq!(monitor.cursor_position(), (COLUMNS - 1, ROWS - 1));
+
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn set_cursor_position_clamps() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+
+            monitor.set_cursor_position(10, 3);
+            assert_eq!(monitor.cursor_position(), (10, 3));
+            monitor.set_cursor_position(COLUMNS + 5, ROWS);
+            assert_eq!(monitor.cursor_position(), (COLUMNS - 1, ROWS - 1));
+
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn print_at_restores_cursor() {
+        let (x, y) = BUFFER.lock().cursor_position();
+
+        print_at!(10, 5, "OK");
+        print!("!");
+        {
+            let monitor = BUFFER.lock();
+            assert_eq!(monitor.buffer[5 * COLUMNS + 10].read() & 0x00ff, b'O' as u16);
+            assert_eq!(monitor.buffer[5 * COLUMNS + 11].read() & 0x00ff, b'K' as u16);
+            assert_eq!(monitor.buffer[y * COLUMNS + x].read() & 0x00ff, b'!' as u16);
+        }
+    }
+
+    #[test_case]
+    fn write_colored_restores_colors() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (fg, bg) = (monitor.foreground_color as u8, monitor.background_color as u8);
+            let (x, y) = monitor.cursor_position();
+
+            monitor.write_colored(b'E', Color::Red, Color::Blue);
+            assert_eq!(monitor.buffer[y * COLUMNS + x].read(), vga_char(b'E', Color::Blue, Color::Red));
+            assert_eq!(monitor.foreground_color as u8, fg);
+            assert_eq!(monitor.background_color as u8, bg);
+        }
+    }
+
+    #[test_case]
+    fn sgr_sequences_set_colors() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+            let (fg, bg) = (monitor.foreground_color, monitor.background_color);
+
+            monitor.set_cursor_position(0, 3);
+            monitor.write_str("\x1b[31mX\x1b[0m");
+            assert_eq!(monitor.buffer[3 * COLUMNS].read(), vga_char(b'X', Color::Black, Color::Red));
+            assert_eq!(monitor.foreground_color, Color::White);
+            assert_eq!(monitor.background_color, Color::Black);
+            assert_eq!(monitor.cursor_position(), (1, 3));
+
+            // Split across writes, malformed and unsupported sequences print nothing.
+            monitor.write_str("\x1b[3");
+            monitor.write_str("2;44mY\x1b[2J\x1b(\x1b[5\nZ\x1b[m");
+            assert_eq!(monitor.buffer[3 * COLUMNS + 1].read(), vga_char(b'Y', Color::Blue, Color::Green));
+            assert_eq!(monitor.buffer[3 * COLUMNS + 2].read(), vga_char(b'Z', Color::Blue, Color::Green));
+            assert_eq!(monitor.cursor_position(), (3, 3));
+            assert_eq!(monitor.foreground_color, Color::White);
+
+            monitor.set_foreground_color(&fg);
+            monitor.set_background_color(&bg);
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn color_round_trip() {
+        for value in 0..16u8 {
+            let color = Color::from_nibble(value).unwrap();
+            assert_eq!(Color::try_from(color as u8), Ok(color));
+        }
+        assert_eq!(Color::try_from(16), Err(16));
+    }
+
+    #[test_case]
+    fn clear_row_blanks_row() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+
+            monitor.set_cursor_position(0, 3);
+            monitor.write_str("some text");
+            monitor.clear_row(3);
+
+            let blank_character = vga_char(b' ', monitor.background_color, monitor.foreground_color);
+            for column in 0..COLUMNS {
+                assert_eq!(monitor.buffer[3 * COLUMNS + column].read(), blank_character);
+            }
+
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn write_hex_and_dec() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+
+            monitor.set_cursor_position(0, 3);
+            monitor.write_hex(0xDEADBEEF);
+            monitor.write_byte(b' ');
+            monitor.write_dec(42);
+            monitor.write_byte(b' ');
+            monitor.write_hex(0);
+
+            let expected = b"0xDEADBEEF 42 0x0";
+            for (column, &byte) in expected.iter().enumerate() {
+                assert_eq!(monitor.buffer[3 * COLUMNS + column].read() & 0x00ff, byte as u16);
+            }
+
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn flush_writes_changed_cells() {
+        {
+            let mut monitor = BUFFER.lock();
+            let (x, y) = monitor.cursor_position();
+            let marker = vga_char(b'#', Color::Black, Color::White);
+
+            monitor.set_double_buffered(true);
+            monitor.set_cursor_position(0, 3);
+            monitor.write_str("ab");
+            assert_eq!(monitor.back_buffer[3 * COLUMNS] & 0x00ff, b'a' as u16);
+            assert!(monitor.dirty_rows[3] && !monitor.dirty_rows[4]);
+
+            // Cells the flush must leave alone: unchanged ones in a dirty row
+            // and any in a clean row.
+            monitor.buffer[3 * COLUMNS + 5].write(marker);
+            monitor.buffer[4 * COLUMNS].write(marker);
+            monitor.flush();
+
+            assert_eq!(monitor.buffer[3 * COLUMNS].read() & 0x00ff, b'a' as u16);
+            assert_eq!(monitor.buffer[3 * COLUMNS + 1].read() & 0x00ff, b'b' as u16);
+            assert_eq!(monitor.buffer[3 * COLUMNS + 5].read(), marker);
+            assert_eq!(monitor.buffer[4 * COLUMNS].read(), marker);
+            assert!(monitor.dirty_rows.iter().all(|dirty| !dirty));
+
+            monitor.set_double_buffered(false);
+            monitor.set_cursor_position(x, y);
+        }
+    }
+
+    #[test_case]
+    fn hide_and_show_cursor() {
+        {
+            let mut monitor = BUFFER.lock();
+            monitor.hide_cursor();
+            assert!(!monitor.cursor.visible);
+            monitor.write_str("hidden");
+            monitor.show_cursor();
+            assert!(monitor.cursor.visible);
+        }
+    }
+
+    /// Returns monitor writing to a 10x4 buffer in memory. Every call hands out
+    /// the same buffer, so monitors of two calls must not be used at once.
+    #[cfg(test)]
+    fn mock_monitor() -> Monitor {
+        static mut MOCK: [u16; 40] = [0; 40];
+        unsafe { Monitor::new(core::ptr::addr_of_mut!(MOCK).cast(), BufferDimensions { cols: 10, rows: 4 }) }
+    }
+
+    #[test_case]
+    fn mock_monitor_scrolls() {
+        let mut monitor = mock_monitor();
+        monitor.clear();
+        assert_eq!(monitor.dimensions(), BufferDimensions { cols: 10, rows: 4 });
+
+        let written = monitor.write_str("0\n1\n2\n3");
+        assert!(!written.scrolled);
+        assert_eq!(monitor.write_str("456789abc"), Written { bytes: 9, scrolled: true });
+        assert_eq!(monitor.cursor_position(), (0, 3));
+        assert_eq!([0, 1, 2].map(|y| monitor.read_char_at(0, y)), [b'1', b'2', b'3']);
+        assert_eq!(monitor.read_char_at(9, 2), b'c');
+        assert_eq!(monitor.read_char_at(0, 3), b' ');
+
+        // Rows are as wide as the screen, so the last column moves along.
+        monitor.set_scroll_region(1, 3);
+        monitor.write_str("\n");
+        assert_eq!([0, 1, 2].map(|y| monitor.read_char_at(0, y)), [b'1', b'3', b' ']);
+        assert_eq!(monitor.read_char_at(9, 1), b'c');
+    }
+
+    #[test_case]
+    fn writes_past_non_scrolling_screen_fail() {
+        use core::fmt::Write;
+
+        let mut monitor = mock_monitor();
+        monitor.clear();
+        monitor.set_scrolling(false);
+        monitor.set_cursor_position(0, 3);
+        assert_eq!(monitor.try_write_str("abc"), Ok(()));
+        assert_eq!(monitor.try_write_str("defghij"), Err(VgaError::OutOfBounds));
+        assert_eq!(monitor.cursor_position(), (9, 3));
+        assert_eq!((monitor.read_char_at(8, 3), monitor.read_char_at(9, 3)), (b'i', b' '));
+        assert!(writeln!(monitor).is_err());
+        assert_eq!(monitor.read_char_at(0, 2), b' ');
+
+        monitor.set_double_buffered(true);
+        assert_eq!(monitor.check_flushed(), Ok(()));
+        monitor.set_scrolling(true);
+        assert_eq!(monitor.try_write_str("j"), Ok(()));
+        assert_eq!(monitor.check_flushed(), Err(VgaError::NotFlushed));
+        monitor.flush();
+        assert_eq!(monitor.check_flushed(), Ok(()));
+        monitor.set_double_buffered(false);
+        assert_eq!(monitor.read_char_at(9, 2), b'j');
+    }
+
+    /// Writes to the cell right of the first row, on a monitor drawing to memory
+    /// instead of the display.
+    #[cfg(test)]
+    fn set_cell_outside_of_screen() {
+        let mut monitor = mock_monitor();
+        monitor.set_cell(10, 0, vga_char(b'x', Color::Black, Color::White));
+    }
+
+    #[test_case]
+    static SET_CELL_OUTSIDE_OF_SCREEN: crate::test::ShouldPanic = crate::test::ShouldPanic {
+        name: concat!(module_path!(), "::set_cell_outside_of_screen"),
+        test: set_cell_outside_of_screen,
+    };
 }
@@ -1,15 +1,276 @@
-#[used]
-#[link_section = ".multiboot"]
-pub static MAGIC: u32 = 0x1BADB002;
-#[link_section = ".multiboot"]
-pub static FLAGS: u32 = 1 << 1 | 1 << 0;
-#[link_section = ".multiboot"]
-pub static CHECKSUM: u32 = (-((MAGIC + FLAGS) as i32) as u32);
-
-#[link_section = ".multiboot"]
-asm!(".set ALIGN,    1<<0             /* align loaded modules on page boundaries */
-.set MEMINFO,  1<<1             /* provide memory map */
-.set FLAGS,    ALIGN | MEMINFO  /* this is the Multiboot 'flag' field */
-.set MAGIC,    0x1BADB002       /* 'magic number' lets bootloader find the header */
-.set CHECKSUM, -(MAGIC + FLAGS) /* checksum of above, to prove we are multiboot */"
-);
+//! Boot information passed by a multiboot (version 1) compliant bootloader such as GRUB.
+//!
+//! The multiboot header requesting it is in `src/boot.s`.
+use core::mem::size_of;
+
+use crate::essentials::Once;
+
+/// Value the bootloader leaves in `eax`, proving it is multiboot compliant.
+pub const BOOTLOADER_MAGIC: u32 = 0x2badb002;
+
+/// `BootInfo::flags` bit: `cmdline` field is valid.
+const FLAG_COMMAND_LINE: u32 = 1 << 2;
+/// `BootInfo::flags` bit: `mmap_*` fields are valid.
+const FLAG_MEMORY_MAP: u32 = 1 << 6;
+
+/// Boot information validated by [`init`].
+static BOOT_INFO: Once<&'static BootInfo> = Once::new();
+
+/// Validates `magic` and stores `info` for [`boot_info`].
+/// Panics if `magic` isn't [`BOOTLOADER_MAGIC`], the kernel can't run without it.
+///
+/// # Safety
+/// Same as [`BootInfo::from_ptr`].
+pub unsafe fn init(magic: u32, info: *const BootInfo) -> &'static BootInfo {
+    assert!(
+        magic == BOOTLOADER_MAGIC,
+        "not loaded by a multiboot bootloader: magic {:#x}, expected {:#x}",
+        magic, BOOTLOADER_MAGIC,
+    );
+    BOOT_INFO.call_once(|| BootInfo::from_ptr(info))
+}
+
+/// Returns boot information stored by [`init`].
+// Only the test runner reads the command line after boot so far.
+#[allow(dead_code)]
+pub fn boot_info() -> Option<&'static BootInfo> {
+    BOOT_INFO.get().copied()
+}
+
+/// Multiboot information structure, as placed in memory by the bootloader.
+///
+/// Fields are only valid if their bit in `flags` is set.
+#[repr(C)]
+#[derive(Debug)]
+pub struct BootInfo {
+    flags: u32,
+    mem_lower: u32,
+    mem_upper: u32,
+    boot_device: u32,
+    cmdline: u32,
+    mods_count: u32,
+    mods_addr: u32,
+    syms: [u32; 4],
+    mmap_length: u32,
+    mmap_addr: u32,
+    drives_length: u32,
+    drives_addr: u32,
+    config_table: u32,
+    boot_loader_name: u32,
+}
+
+impl BootInfo {
+    /// Returns boot information at `address`.
+    ///
+    /// # Safety
+    /// `address` must point to a multiboot information structure
+    /// that stays valid and unchanged for the rest of the kernel's life.
+    pub unsafe fn from_ptr(address: *const BootInfo) -> &'static BootInfo {
+        &*address
+    }
+
+    /// Returns kernel command line passed by the bootloader. `None` if there is none
+    /// or it isn't valid UTF-8.
+    pub fn command_line(&self) -> Option<&str> {
+        if self.flags & FLAG_COMMAND_LINE == 0 || self.cmdline == 0 {
+            return None;
+        }
+
+        let start = self.cmdline as *const u8;
+        let mut length = 0;
+        // The command line is a NUL terminated C string.
+        while unsafe { *start.add(length) } != 0 {
+            length += 1;
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(start, length) };
+        core::str::from_utf8(bytes).ok()
+    }
+
+    /// Returns options of the kernel command line, see [`parse_options`].
+    pub fn command_line_options(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        parse_options(self.command_line().unwrap_or(""))
+    }
+
+    /// Returns physical memory range holding the memory map, empty without one.
+    pub fn memory_map_range(&self) -> core::ops::Range<u64> {
+        if self.flags & FLAG_MEMORY_MAP == 0 {
+            return 0..0;
+        }
+        let start = self.mmap_addr as u64;
+        start..start + self.mmap_length as u64
+    }
+
+    /// Returns memory areas reported by the bootloader,
+    /// no areas if it didn't provide a memory map.
+    pub fn memory_map(&self) -> impl Iterator<Item = MemoryArea> {
+        let (start, length) = if self.flags & FLAG_MEMORY_MAP != 0 {
+            (self.mmap_addr as usize, self.mmap_length as usize)
+        } else {
+            (0, 0)
+        };
+
+        MemoryMapIter {
+            current: start,
+            end: start + length,
+        }
+    }
+}
+
+/// Splits `command_line` on whitespace into `key=value` options.
+/// Options without `=` have no value, e.g. `testmode` is `("testmode", None)`.
+///
+/// GRUB puts the kernel's path first, which becomes an option without value too.
+pub fn parse_options(command_line: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    command_line.split_whitespace().map(|option| match option.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (option, None),
+    })
+}
+
+/// Kind of a [`MemoryArea`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// RAM free for use.
+    Available,
+    /// Unusable, also used for unknown kinds.
+    Reserved,
+    /// Holds ACPI tables, usable once they are read.
+    AcpiReclaimable,
+    /// Must be preserved across sleep states.
+    AcpiNvs,
+    /// Defective RAM.
+    BadMemory,
+}
+
+impl From<u32> for MemoryKind {
+    fn from(kind: u32) -> Self {
+        match kind {
+            1 => MemoryKind::Available,
+            3 => MemoryKind::AcpiReclaimable,
+            4 => MemoryKind::AcpiNvs,
+            5 => MemoryKind::BadMemory,
+            _ => MemoryKind::Reserved,
+        }
+    }
+}
+
+/// Physical memory area from the memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryArea {
+    pub base_addr: u64,
+    pub length: u64,
+    pub kind: MemoryKind,
+}
+
+/// Memory map entry, as placed in memory by the bootloader.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct MemoryMapEntry {
+    /// Size of the rest of the entry, may be bigger than the fields below.
+    size: u32,
+    base_addr: u64,
+    length: u64,
+    kind: u32,
+}
+
+/// Iterator over the entries of the memory map between `current` and `end`.
+struct MemoryMapIter {
+    current: usize,
+    end: usize,
+}
+
+impl Iterator for MemoryMapIter {
+    type Item = MemoryArea;
+
+    fn next(&mut self) -> Option<MemoryArea> {
+        if self.current + size_of::<MemoryMapEntry>() > self.end {
+            return None;
+        }
+
+        // Entries are only 4 byte aligned.
+        let entry = unsafe { (self.current as *const MemoryMapEntry).read_unaligned() };
+        self.current += size_of::<u32>() + entry.size as usize;
+
+        Some(MemoryArea {
+            base_addr: entry.base_addr,
+            length: entry.length,
+            kind: MemoryKind::from(entry.kind),
+        })
+    }
+}
+
+#[cfg(test)]
+const ENTRY_SIZE: u32 = (size_of::<MemoryMapEntry>() - size_of::<u32>()) as u32;
+
+/// Calls `f` with boot information whose memory map consists of up to 8 `areas`.
+#[cfg(test)]
+pub fn test_boot_info(areas: &[MemoryArea], f: impl FnOnce(&BootInfo)) {
+    let mut entries = [MemoryMapEntry { size: ENTRY_SIZE, base_addr: 0, length: 0, kind: 0 }; 8];
+    assert!(areas.len() <= entries.len());
+    for (entry, area) in entries.iter_mut().zip(areas) {
+        entry.base_addr = area.base_addr;
+        entry.length = area.length;
+        entry.kind = match area.kind {
+            MemoryKind::Available => 1,
+            MemoryKind::Reserved => 2,
+            MemoryKind::AcpiReclaimable => 3,
+            MemoryKind::AcpiNvs => 4,
+            MemoryKind::BadMemory => 5,
+        };
+    }
+
+    let mut info: BootInfo = unsafe { core::mem::zeroed() };
+    info.flags = FLAG_MEMORY_MAP;
+    info.mmap_addr = entries.as_ptr() as u32;
+    info.mmap_length = (areas.len() * size_of::<MemoryMapEntry>()) as u32;
+    f(&info);
+}
+
+#[test_case]
+fn memory_map_is_parsed() {
+    let entries = [
+        MemoryMapEntry { size: ENTRY_SIZE, base_addr: 0, length: 0x9fc00, kind: 1 },
+        MemoryMapEntry { size: ENTRY_SIZE, base_addr: 0xf0000, length: 0x10000, kind: 2 },
+        MemoryMapEntry { size: ENTRY_SIZE, base_addr: 0x100000, length: 0x7ee0000, kind: 1 },
+        MemoryMapEntry { size: ENTRY_SIZE, base_addr: 0x7fe0000, length: 0x20000, kind: 3 },
+    ];
+    let mut info: BootInfo = unsafe { core::mem::zeroed() };
+    info.flags = FLAG_MEMORY_MAP;
+    info.mmap_addr = entries.as_ptr() as u32;
+    info.mmap_length = core::mem::size_of_val(&entries) as u32;
+
+    let mut areas = info.memory_map();
+    assert_eq!(areas.next(), Some(MemoryArea { base_addr: 0, length: 0x9fc00, kind: MemoryKind::Available }));
+    assert_eq!(areas.next().map(|area| area.kind), Some(MemoryKind::Reserved));
+    assert_eq!(areas.next().map(|area| area.base_addr), Some(0x100000));
+    assert_eq!(areas.next().map(|area| area.kind), Some(MemoryKind::AcpiReclaimable));
+    assert_eq!(areas.next(), None);
+
+    info.flags = 0;
+    assert_eq!(info.memory_map().count(), 0);
+}
+
+#[test_case]
+fn command_line_is_read() {
+    let command_line = b"/boot/kernel serial=on  testmode\0";
+    let mut info: BootInfo = unsafe { core::mem::zeroed() };
+    assert_eq!(info.command_line(), None);
+
+    info.flags = FLAG_COMMAND_LINE;
+    assert_eq!(info.command_line(), None);
+
+    info.cmdline = command_line.as_ptr() as u32;
+    assert_eq!(info.command_line(), Some("/boot/kernel serial=on  testmode"));
+
+    let mut options = info.command_line_options();
+    assert_eq!(options.next(), Some(("/boot/kernel", None)));
+    assert_eq!(options.next(), Some(("serial", Some("on"))));
+    assert_eq!(options.next(), Some(("testmode", None)));
+    assert_eq!(options.next(), None);
+}
+
+#[test_case]
+fn boot_info_has_available_memory() {
+    let info = boot_info().unwrap();
+    assert!(info.memory_map().any(|area| area.kind == MemoryKind::Available));
+}
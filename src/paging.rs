@@ -0,0 +1,402 @@
+//! 32-bit paging: a page directory of 1024 entries, each pointing to a page
+//! table of 1024 entries that map 4 KiB pages.
+//!
+//! The bootloader leaves paging disabled. [`init`] enables it with all physical
+//! memory identity mapped, except the null page. Without PAE there is no
+//! no-execute bit, all mapped pages are executable.
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::ops::{BitOr, Index, IndexMut};
+
+use bit_field::BitField;
+
+use crate::frame_allocator::{FrameAllocator, PhysFrame, FRAME_SIZE};
+use crate::registers;
+
+/// Size of a page in bytes.
+pub const PAGE_SIZE: usize = 4096;
+/// Number of entries of a page directory or a page table.
+const ENTRIES: usize = 1024;
+
+/// `CR0` bit enabling paging.
+const CR0_PAGING: usize = 1 << 31;
+/// `CR0` bit making read-only pages read-only for the kernel too.
+const CR0_WRITE_PROTECT: usize = 1 << 16;
+
+/// Flags of a [`PageTableEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageTableFlags(u32);
+
+impl PageTableFlags {
+    /// The entry maps a page or table.
+    pub const PRESENT: Self = Self(1 << 0);
+    /// Writes are allowed.
+    pub const WRITABLE: Self = Self(1 << 1);
+    /// Ring 3 may access the memory.
+    pub const USER: Self = Self(1 << 2);
+    /// Writes go through the cache straight to memory.
+    #[allow(dead_code)]
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    /// The memory is not cached, for memory-mapped devices.
+    pub const NO_CACHE: Self = Self(1 << 4);
+
+    #[allow(dead_code)]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns whether all flags of `other` are set.
+    #[allow(dead_code)]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for PageTableFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Entry of a page directory or a page table: address of a frame and flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PageTableEntry(u32);
+
+impl PageTableEntry {
+    /// Bits of the entry holding the frame address.
+    const ADDRESS: u32 = !0xfff;
+
+    pub const fn unused() -> Self {
+        Self(0)
+    }
+
+    /// Returns entry mapping `frame` with `flags`.
+    pub fn new(frame: PhysFrame, flags: PageTableFlags) -> Self {
+        Self(frame.start_address() as u32 & Self::ADDRESS | flags.0)
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_writable(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_user(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    /// Returns the mapped frame, if the entry is present.
+    pub fn frame(&self) -> Option<PhysFrame> {
+        self.is_present().then(|| PhysFrame::containing_address((self.0 & Self::ADDRESS) as u64))
+    }
+
+    #[allow(dead_code)]
+    pub fn flags(&self) -> PageTableFlags {
+        PageTableFlags(self.0 & !Self::ADDRESS)
+    }
+}
+
+/// Page directory or page table, both occupy exactly one frame.
+#[repr(C, align(4096))]
+pub struct PageTable([PageTableEntry; ENTRIES]);
+
+impl PageTable {
+    pub const fn new() -> Self {
+        Self([PageTableEntry::unused(); ENTRIES])
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    fn index(&self, index: usize) -> &PageTableEntry {
+        &self.0[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    fn index_mut(&mut self, index: usize) -> &mut PageTableEntry {
+        &mut self.0[index]
+    }
+}
+
+/// Virtual memory page of [`PAGE_SIZE`] bytes, represented by its aligned start address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page(usize);
+
+impl Page {
+    /// Returns page containing virtual `address`.
+    pub const fn containing_address(address: usize) -> Self {
+        Self(address & !(PAGE_SIZE - 1))
+    }
+
+    /// Returns virtual address of the first byte of the page.
+    pub const fn start_address(&self) -> usize {
+        self.0
+    }
+
+    /// Returns index of the page directory entry covering the page.
+    const fn directory_index(&self) -> usize {
+        self.0 >> 22
+    }
+
+    /// Returns index of the page table entry mapping the page.
+    const fn table_index(&self) -> usize {
+        (self.0 >> 12) & (ENTRIES - 1)
+    }
+}
+
+/// Error of [`Mapper::map_to`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// The page already maps the contained frame.
+    AlreadyMapped(PhysFrame),
+    /// No frame for a new page table was left.
+    FrameAllocationFailed,
+}
+
+/// Error of [`Mapper::unmap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmapError {
+    NotMapped,
+}
+
+/// Removes the TLB entry of the page containing `address` (`invlpg`).
+#[inline]
+pub fn flush(address: usize) {
+    unsafe {
+    asm!("invlpg [{}]", in(reg) address, options(nostack, preserves_flags));
+    }
+}
+
+/// Edits the mappings of a page directory, whose page tables are accessible
+/// at their physical address plus an offset.
+pub struct Mapper<'a> {
+    directory: &'a mut PageTable,
+    /// Virtual address of physical address 0.
+    offset: usize,
+}
+
+impl<'a> Mapper<'a> {
+    /// Creates mapper for `directory`.
+    ///
+    /// # Safety
+    /// All physical memory holding page tables of `directory` must be mapped at
+    /// its address plus `offset`. No other reference to the tables may exist.
+    pub unsafe fn new(directory: &'a mut PageTable, offset: usize) -> Self {
+        Self { directory, offset }
+    }
+
+    /// Returns page table the directory `entry` points to.
+    fn table(&self, entry: PageTableEntry) -> Option<&'a mut PageTable> {
+        let frame = entry.frame()?;
+        Some(unsafe { &mut *((frame.start_address() as usize + self.offset) as *mut PageTable) })
+    }
+
+    /// Returns physical address `address` is mapped to.
+    #[allow(dead_code)]
+    pub fn translate(&self, address: usize) -> Option<u64> {
+        let page = Page::containing_address(address);
+        let table = self.table(self.directory[page.directory_index()])?;
+        let frame = table[page.table_index()].frame()?;
+        Some(frame.start_address() + (address - page.start_address()) as u64)
+    }
+
+    /// Maps `page` to `frame` with `flags`, which should include
+    /// [`PageTableFlags::PRESENT`]. Missing page tables are taken from `allocator`.
+    ///
+    /// # Safety
+    /// Changing a mapping can make references point to different memory.
+    pub unsafe fn map_to(&mut self, page: Page, frame: PhysFrame, flags: PageTableFlags,
+                         allocator: &mut impl FrameAllocator) -> Result<(), MapError> {
+        let directory_entry = &mut self.directory[page.directory_index()];
+        if !directory_entry.is_present() {
+            let table_frame = allocator.allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+            let table = (table_frame.start_address() as usize + self.offset) as *mut PageTable;
+            table.write(PageTable::new());
+            // Permissions of the page table entries restrict further.
+            let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER;
+            *directory_entry = PageTableEntry::new(table_frame, table_flags);
+        }
+
+        let table = self.table(self.directory[page.directory_index()]).unwrap();
+        let entry = &mut table[page.table_index()];
+        if let Some(mapped) = entry.frame() {
+            return Err(MapError::AlreadyMapped(mapped));
+        }
+        *entry = PageTableEntry::new(frame, flags);
+        flush(page.start_address());
+        Ok(())
+    }
+
+    /// Removes mapping of `page` and returns the frame it mapped.
+    /// Page tables are kept even when they become empty.
+    ///
+    /// # Safety
+    /// Nothing may reference the memory of `page` anymore.
+    pub unsafe fn unmap(&mut self, page: Page) -> Result<PhysFrame, UnmapError> {
+        let table = self.table(self.directory[page.directory_index()]).ok_or(UnmapError::NotMapped)?;
+        let entry = &mut table[page.table_index()];
+        let frame = entry.frame().ok_or(UnmapError::NotMapped)?;
+        *entry = PageTableEntry::unused();
+        flush(page.start_address());
+        Ok(frame)
+    }
+}
+
+/// Returns lowest address of the stack the kernel boots on, defined in `boot.s`.
+pub fn kernel_stack_bottom() -> usize {
+    extern "C" {
+        static stack_bottom: u8;
+    }
+    unsafe { &stack_bottom as *const u8 as usize }
+}
+
+/// Start address of the page installed by [`install_guard_page`], `0` if none.
+static GUARD_PAGE: AtomicUsize = AtomicUsize::new(0);
+
+/// Unmaps the page below the stack whose lowest address is `stack_bottom`,
+/// so a stack overflow page faults instead of overwriting memory below the stack.
+///
+/// Delivering that page fault needs the exhausted stack too, so it escalates
+/// to a double fault, which runs on its own stack and reports the overflow.
+///
+/// # Safety
+/// The page below `stack_bottom` must be reserved for the guard page and
+/// [`active_mapper`] must be safe to call.
+pub unsafe fn install_guard_page(stack_bottom: usize) -> Result<Page, UnmapError> {
+    let page = Page::containing_address(stack_bottom - PAGE_SIZE);
+    // The frame stays part of the kernel image, so it isn't freed.
+    active_mapper().unmap(page)?;
+    GUARD_PAGE.store(page.start_address(), Ordering::Relaxed);
+    Ok(page)
+}
+
+/// Returns whether `address` lies in the page installed by [`install_guard_page`].
+pub fn is_guard_page(address: usize) -> bool {
+    let guard = GUARD_PAGE.load(Ordering::Relaxed);
+    guard != 0 && Page::containing_address(address).start_address() == guard
+}
+
+/// Returns mapper of the active page directory found through `CR3`.
+///
+/// # Safety
+/// Paging must be enabled by [`init`], whose identity mapping makes page tables
+/// accessible. Only one returned mapper may be used at a time.
+pub unsafe fn active_mapper() -> Mapper<'static> {
+    let directory = (registers::read_cr3() & !0xfff) as *mut PageTable;
+    Mapper::new(&mut *directory, 0)
+}
+
+/// Enables paging with a new page directory that identity maps physical memory
+/// from the second page up to `memory_end` with writable kernel pages.
+/// The tables are taken from `allocator`.
+///
+/// # Safety
+/// All memory the kernel uses must lie below `memory_end` and `allocator` must
+/// only return frames below it. Must be called only once.
+pub unsafe fn init(memory_end: u64, allocator: &mut impl FrameAllocator) -> Result<(), MapError> {
+    let directory_frame = allocator.allocate_frame().ok_or(MapError::FrameAllocationFailed)?;
+    let directory = directory_frame.start_address() as usize as *mut PageTable;
+    directory.write(PageTable::new());
+
+    // Paging is still disabled, so physical addresses are used directly.
+    let mut mapper = Mapper::new(&mut *directory, 0);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    // The null page stays unmapped, so null pointer accesses fault.
+    for address in (FRAME_SIZE..memory_end).step_by(PAGE_SIZE) {
+        let page = Page::containing_address(address as usize);
+        mapper.map_to(page, PhysFrame::containing_address(address), flags, allocator)?;
+    }
+
+    registers::write_cr3(directory as usize);
+    registers::write_cr0(registers::read_cr0() | CR0_PAGING | CR0_WRITE_PROTECT);
+
+    // The double fault task switches address space to its own `cr3`.
+    crate::gdt::DOUBLE_FAULT_TSS.cr3 = registers::read_cr3() as u32;
+    Ok(())
+}
+
+#[test_case]
+fn entry_flags() {
+    let frame = PhysFrame::containing_address(0x12_3000);
+    let entry = PageTableEntry::new(frame, PageTableFlags::PRESENT | PageTableFlags::USER);
+    assert!(entry.is_present() && entry.is_user() && !entry.is_writable());
+    assert_eq!(entry.frame(), Some(frame));
+    assert!(entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::USER));
+    assert!(!entry.flags().contains(PageTableFlags::WRITABLE));
+    assert_eq!(PageTableEntry::unused().frame(), None);
+
+    let page = Page::containing_address(0xc040_5123);
+    assert_eq!((page.directory_index(), page.table_index()), (0x301, 0x5));
+}
+
+#[test_case]
+fn map_write_read_unmap() {
+    use crate::frame_allocator::FRAME_ALLOCATOR;
+    use crate::interrupts::try_read;
+
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let mut mapper = unsafe { active_mapper() };
+    let frame = allocator.allocate_frame().unwrap();
+    // Far above the identity mapped memory.
+    let page = Page::containing_address(0xd000_0000);
+    let address = page.start_address() + 8;
+
+    assert_eq!(try_read(address), Err(address));
+    unsafe {
+        mapper.map_to(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE, allocator).unwrap();
+        core::ptr::write_volatile(address as *mut u32, 0xcafe_f00d);
+    }
+    assert_eq!(try_read(address), Ok(0xcafe_f00d));
+    assert_eq!(mapper.translate(address), Some(frame.start_address() + 8));
+    // Identity mapped, so the frame's memory is also visible at its physical address.
+    assert_eq!(try_read(frame.start_address() as usize + 8), Ok(0xcafe_f00d));
+    assert_eq!(unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT, allocator) },
+               Err(MapError::AlreadyMapped(frame)));
+
+    assert_eq!(unsafe { mapper.unmap(page) }, Ok(frame));
+    assert_eq!(try_read(address), Err(address));
+    assert_eq!(unsafe { mapper.unmap(page) }, Err(UnmapError::NotMapped));
+    allocator.deallocate_frame(frame);
+}
+
+#[test_case]
+fn guard_page_faults() {
+    let guard = kernel_stack_bottom() - PAGE_SIZE;
+    assert!(is_guard_page(guard) && is_guard_page(kernel_stack_bottom() - 1));
+    assert!(!is_guard_page(kernel_stack_bottom()));
+    assert_eq!(crate::interrupts::try_read(guard + 16), Err(guard + 16));
+}
+
+/// Recurses on the kernel stack until it runs into the guard page.
+#[cfg(test)]
+fn kernel_stack_overflow() {
+    unsafe {
+    asm!(
+        "2:",
+        "call 2b",
+        options(noreturn),
+    );
+    }
+}
+
+#[test_case]
+static KERNEL_STACK_OVERFLOW: crate::test::ShouldPanic = crate::test::ShouldPanic {
+    name: concat!(module_path!(), "::kernel_stack_overflow"),
+    test: kernel_stack_overflow,
+};
+
+#[test_case]
+fn null_page_faults() {
+    // Also shows `CR2` holds the exact faulting address.
+    assert_eq!(crate::interrupts::try_read(0x10), Err(0x10));
+}
@@ -0,0 +1,148 @@
+//! Post-mortem information printed by the panic handlers.
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::serial_println;
+
+/// Most return addresses printed by [`dump`].
+const MAX_FRAMES: usize = 16;
+
+/// Set by the panic handlers, makes released locks poison their mutex.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Records whether a panic is running, see [`crate::essentials::Mutex`].
+pub fn set_panicking(panicking: bool) {
+    PANICKING.store(panicking, Ordering::Relaxed);
+}
+
+/// Returns whether a panic is running.
+pub fn is_panicking() -> bool {
+    PANICKING.load(Ordering::Relaxed)
+}
+
+/// General purpose registers, stack pointer and instruction pointer.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub eip: u32,
+}
+
+impl Registers {
+    /// Returns registers at the point of the call.
+    ///
+    /// The register holding the address of the result is overwritten before
+    /// it is saved, so its value is unreliable.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut registers = Self::default();
+        unsafe {
+        asm!(
+            "mov [{0}], eax",
+            "mov [{0} + 4], ebx",
+            "mov [{0} + 8], ecx",
+            "mov [{0} + 12], edx",
+            "mov [{0} + 16], esi",
+            "mov [{0} + 20], edi",
+            "mov [{0} + 24], ebp",
+            "mov [{0} + 28], esp",
+            // `eip` can only be read by pushing it.
+            "call 2f",
+            "2:",
+            "pop dword ptr [{0} + 32]",
+            in(reg) &mut registers as *mut Self,
+            options(preserves_flags),
+        );
+        }
+        registers
+    }
+}
+
+/// Calls `emit` with the return address of every frame in the frame
+/// pointer chain starting at `ebp`, innermost first, up to `max_frames` of them.
+///
+/// The walk stops at a null or misaligned frame pointer, at one outside the
+/// kernel image, which holds all kernel stacks, and at one not above the
+/// previous frame. Code built without frame pointers leaves such values.
+///
+/// # Safety
+/// Every frame pointer passing these checks must be readable.
+pub unsafe fn walk_frames(mut ebp: usize, max_frames: usize, mut emit: impl FnMut(usize)) {
+    let stacks = crate::frame_allocator::kernel_image();
+    for _ in 0..max_frames {
+        if ebp == 0 || !ebp.is_multiple_of(4) {
+            return;
+        }
+        let frame_end = ebp as u64 + 2 * core::mem::size_of::<usize>() as u64;
+        if (ebp as u64) < stacks.start || frame_end > stacks.end {
+            return;
+        }
+        // Saved `ebp` of the caller, followed by the return address into it.
+        let frame = ebp as *const usize;
+        emit(*frame.add(1));
+
+        let next = *frame;
+        // The stack grows down, so callers' frames lie higher.
+        if next <= ebp {
+            return;
+        }
+        ebp = next;
+    }
+}
+
+/// Prints `registers` and the return addresses of their frame pointer chain to serial.
+pub fn dump(registers: &Registers) {
+    let r = registers;
+    serial_println!("eax={:08x} ebx={:08x} ecx={:08x} edx={:08x}", r.eax, r.ebx, r.ecx, r.edx);
+    serial_println!("esi={:08x} edi={:08x} ebp={:08x} esp={:08x}", r.esi, r.edi, r.ebp, r.esp);
+    serial_println!("eip={:08x}", r.eip);
+
+    serial_println!("backtrace:");
+    let mut frames = 0;
+    unsafe {
+    walk_frames(r.ebp as usize, MAX_FRAMES, |address| {
+        serial_println!("  {:#010x}", address);
+        frames += 1;
+    });
+    }
+    if frames == 0 {
+        serial_println!("  unavailable, no frame pointers");
+    }
+}
+
+#[test_case]
+fn frames_are_walked() {
+    // Three frames with saved `ebp` and return address each, the last one ending the chain.
+    let mut stack = [0usize; 6];
+    let base = stack.as_ptr() as usize;
+    let word = core::mem::size_of::<usize>();
+    stack[0] = base + 2 * word;
+    stack[1] = 0x1000;
+    stack[2] = base + 4 * word;
+    stack[3] = 0x2000;
+    stack[4] = 0;
+    stack[5] = 0x3000;
+
+    let mut addresses = [0; 4];
+    let mut count = 0;
+    unsafe {
+    walk_frames(stack.as_ptr() as usize, 4, |address| {
+        addresses[count] = address;
+        count += 1;
+    });
+    }
+    assert_eq!(&addresses[..count], &[0x1000, 0x2000, 0x3000]);
+
+    count = 0;
+    unsafe { walk_frames(base, 2, |_| count += 1) };
+    assert_eq!(count, 2);
+    unsafe { walk_frames(0, 4, |_| count += 1) };
+    assert_eq!(count, 2);
+}
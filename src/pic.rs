@@ -0,0 +1,185 @@
+//! Driver for the two chained 8259 programmable interrupt controllers (PIC).
+//!
+//! The master PIC handles IRQs 0–7, the slave IRQs 8–15 and is connected
+//! to IRQ 2 of the master.
+use core::sync::atomic::Ordering;
+
+use crate::interrupts::{self, InterruptStackFrame};
+use crate::port::{io_wait, Port};
+
+/// Vector IRQ 0 is delivered on, once [`remap`]ped by `_start`.
+pub const PIC1_OFFSET: u8 = 0x20;
+/// Vector IRQ 8 is delivered on, once [`remap`]ped by `_start`.
+pub const PIC2_OFFSET: u8 = 0x28;
+
+const PIC1_COMMAND: Port<u8> = Port::new(0x20);
+const PIC1_DATA: Port<u8> = Port::new(0x21);
+const PIC2_COMMAND: Port<u8> = Port::new(0xa0);
+const PIC2_DATA: Port<u8> = Port::new(0xa1);
+
+/// ICW1: start initialization, ICW4 will be sent.
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086/88 mode.
+const ICW4_8086: u8 = 0x01;
+/// End-of-interrupt command.
+const EOI: u8 = 0x20;
+/// OCW3: make the next read of the command port return the in-service register.
+const OCW3_READ_ISR: u8 = 0x0b;
+
+/// Lowest priority IRQ of the master, raised when an IRQ disappears before being acknowledged.
+pub const SPURIOUS_IRQ_MASTER: u8 = 7;
+/// Lowest priority IRQ of the slave, see [`SPURIOUS_IRQ_MASTER`].
+pub const SPURIOUS_IRQ_SLAVE: u8 = 15;
+
+/// Reinitializes both PICs so IRQs 0–7 are delivered on vectors
+/// `offset1..offset1 + 8` and IRQs 8–15 on `offset2..offset2 + 8`.
+///
+/// The BIOS maps IRQs 0–7 to vectors 8–15, which collide with CPU exceptions.
+/// Interrupt masks are preserved.
+pub fn remap(offset1: u8, offset2: u8) {
+    let (mask1, mask2) = masks();
+
+    // The PICs expect the initialization words in exactly this order.
+    unsafe {
+        PIC1_COMMAND.write(ICW1_INIT);
+        io_wait();
+        PIC2_COMMAND.write(ICW1_INIT);
+        io_wait();
+
+        // ICW2: vector offsets.
+        PIC1_DATA.write(offset1);
+        io_wait();
+        PIC2_DATA.write(offset2);
+        io_wait();
+
+        // ICW3: slave is connected to IRQ 2 of the master, which is its cascade identity.
+        PIC1_DATA.write(1 << 2);
+        io_wait();
+        PIC2_DATA.write(2);
+        io_wait();
+
+        PIC1_DATA.write(ICW4_8086);
+        io_wait();
+        PIC2_DATA.write(ICW4_8086);
+        io_wait();
+    }
+
+    set_masks(mask1, mask2);
+}
+
+/// Signals the end of handling `irq`. Must be sent at the end of every IRQ handler.
+pub fn send_eoi(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            PIC2_COMMAND.write(EOI);
+        }
+        PIC1_COMMAND.write(EOI);
+    }
+}
+
+/// Returns in-service registers of the slave (high byte) and the master PIC (low byte).
+/// Bit `n` is set while IRQ `n` is being handled, from its delivery to its EOI.
+pub fn in_service() -> u16 {
+    unsafe {
+        PIC1_COMMAND.write(OCW3_READ_ISR);
+        PIC2_COMMAND.write(OCW3_READ_ISR);
+        ( (PIC2_COMMAND.read() as u16) << 8) | PIC1_COMMAND.read() as u16
+    }
+}
+
+/// Returns interrupt masks of the master and the slave PIC.
+/// Bit `n` set means IRQ `n` (`n + 8` for the slave) is masked.
+pub fn masks() -> (u8, u8) {
+    unsafe { (PIC1_DATA.read(), PIC2_DATA.read()) }
+}
+
+/// Sets interrupt masks of the master and the slave PIC, see [`masks`].
+pub fn set_masks(mask1: u8, mask2: u8) {
+    unsafe {
+        PIC1_DATA.write(mask1);
+        PIC2_DATA.write(mask2);
+    }
+}
+
+/// Returns data port of the PIC handling `irq` and the line of `irq` on it.
+fn data_port(irq: u8) -> (Port<u8>, u8) {
+    if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    }
+}
+
+/// Masks `irq`, so it isn't delivered anymore.
+pub fn set_mask(irq: u8) {
+    let (port, line) = data_port(irq);
+    unsafe { port.write(port.read() | (1 << line)) };
+}
+
+/// Unmasks `irq`, so it gets delivered.
+pub fn clear_mask(irq: u8) {
+    let (port, line) = data_port(irq);
+    unsafe { port.write(port.read() & !(1 << line)) };
+}
+
+/// Handles IRQ 7, which is usually spurious.
+///
+/// A PIC that sees an IRQ line drop between raising the interrupt and the CPU
+/// acknowledging it still delivers its lowest priority IRQ, without marking it
+/// in service. Reading the in-service register (OCW3 `0x0b` to the command port,
+/// then reading the command port) tells the cases apart. A spurious IRQ must not
+/// get an EOI, which would end the highest priority IRQ actually in service.
+pub(crate) extern "C" fn spurious_irq_master_handler(_frame: &InterruptStackFrame) {
+    interrupts::count(PIC1_OFFSET + SPURIOUS_IRQ_MASTER);
+
+    if in_service() & (1 << SPURIOUS_IRQ_MASTER) == 0 {
+        interrupts::SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    send_eoi(SPURIOUS_IRQ_MASTER);
+}
+
+/// Handles IRQ 15, see [`spurious_irq_master_handler`].
+///
+/// The master doesn't know the slave's IRQ is spurious, its cascade IRQ 2
+/// is in service. A spurious IRQ 15 therefore still needs an EOI for the master.
+pub(crate) extern "C" fn spurious_irq_slave_handler(_frame: &InterruptStackFrame) {
+    interrupts::count(PIC1_OFFSET + SPURIOUS_IRQ_SLAVE);
+
+    if in_service() & (1 << SPURIOUS_IRQ_SLAVE) == 0 {
+        interrupts::SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        unsafe { PIC1_COMMAND.write(EOI) };
+        return;
+    }
+    send_eoi(SPURIOUS_IRQ_SLAVE);
+}
+
+#[test_case]
+fn software_irqs_are_spurious() {
+    // Nothing is in service outside of IRQ handlers.
+    assert_eq!(in_service(), 0);
+
+    let before = interrupts::SPURIOUS.load(Ordering::Relaxed);
+    unsafe {
+    core::arch::asm!("int 0x27", "int 0x2f");
+    }
+    assert_eq!(interrupts::SPURIOUS.load(Ordering::Relaxed) - before, 2);
+    assert_eq!(in_service(), 0);
+}
+
+#[test_case]
+fn remap_keeps_masks() {
+    let (mask1, mask2) = masks();
+
+    remap(PIC1_OFFSET, PIC2_OFFSET);
+    assert_eq!(masks(), (mask1, mask2));
+
+    set_mask(4);
+    set_mask(12);
+    assert_eq!(masks(), (mask1 | (1 << 4), mask2 | (1 << 4)));
+    clear_mask(4);
+    clear_mask(12);
+    assert_eq!(masks(), (mask1 & !(1 << 4), mask2 & !(1 << 4)));
+
+    set_masks(mask1, mask2);
+}
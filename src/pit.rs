@@ -0,0 +1,154 @@
+//! Driver for the 8253/8254 programmable interval timer (PIT),
+//! the kernel's monotonic time source.
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::interrupts::InterruptStackFrame;
+use crate::pic;
+use crate::port::{Port, PortWriteOnly};
+
+/// Frequency of the oscillator driving the PIT in Hz.
+pub const BASE_FREQUENCY: u32 = 1193182;
+/// Tick frequency set by [`init`] in Hz.
+pub const DEFAULT_FREQUENCY: u32 = 100;
+/// IRQ line channel 0 is connected to.
+pub const TIMER_IRQ: u8 = 0;
+
+const CHANNEL0: Port<u8> = Port::new(0x40);
+const CHANNEL2: Port<u8> = Port::new(0x42);
+const COMMAND: PortWriteOnly<u8> = PortWriteOnly::new(0x43);
+/// Keyboard controller port B, bits 0–1 gate channel 2 to the PC speaker.
+const SPEAKER: Port<u8> = Port::new(0x61);
+/// Bits of [`SPEAKER`] enabling channel 2 and the speaker output.
+const SPEAKER_ENABLE: u8 = 0b11;
+
+/// Low and high half of the number of timer interrupts since [`init`], the
+/// target has no 64-bit atomics. Only [`tick`] writes them, with interrupts
+/// disabled, and [`ticks`] reads them with interrupts disabled too.
+static TICKS_LOW: AtomicU32 = AtomicU32::new(0);
+static TICKS_HIGH: AtomicU32 = AtomicU32::new(0);
+/// Frequency channel 0 runs with in Hz, `0` before it is programmed.
+static FREQUENCY: AtomicU32 = AtomicU32::new(0);
+
+/// Returns divisor making the PIT tick with `frequency` Hz.
+///
+/// The result is clamped to the valid `u16` range. The slowest divisor
+/// the PIT supports (65536) is written as `0`, which is never returned.
+fn divisor(frequency: u32) -> u16 {
+    let divisor = BASE_FREQUENCY / core::cmp::max(frequency, 1);
+    core::cmp::min(core::cmp::max(divisor, 1), u16::MAX as u32) as u16
+}
+
+/// Programs channel 0 to generate an interrupt `frequency` times per second.
+pub fn set_frequency(frequency: u32) {
+    let divisor = divisor(frequency);
+
+    // Channel 0, access low byte then high byte, mode 3 (square wave), binary.
+    unsafe {
+        COMMAND.write(0x36);
+        CHANNEL0.write(divisor as u8);
+        CHANNEL0.write( (divisor >> 8) as u8);
+    }
+    FREQUENCY.store(BASE_FREQUENCY / divisor as u32, Ordering::Relaxed);
+}
+
+/// Returns frequency channel 0 generates interrupts with, which differs from
+/// the one passed to [`set_frequency`] by the rounding of the divisor.
+pub fn frequency() -> u32 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Sounds the PC speaker with `frequency` Hz for `duration_ticks` timer ticks.
+/// Interrupts must be enabled, see [`sleep_ticks`].
+#[allow(dead_code)]
+pub fn beep(frequency: u32, duration_ticks: u64) {
+    let divisor = divisor(frequency);
+
+    // Channel 2, access low byte then high byte, mode 3 (square wave), binary.
+    unsafe {
+        COMMAND.write(0xb6);
+        CHANNEL2.write(divisor as u8);
+        CHANNEL2.write((divisor >> 8) as u8);
+
+        SPEAKER.write(SPEAKER.read() | SPEAKER_ENABLE);
+    }
+    sleep_ticks(duration_ticks);
+    unsafe { SPEAKER.write(SPEAKER.read() & !SPEAKER_ENABLE) };
+}
+
+/// Starts the timer with [`DEFAULT_FREQUENCY`] and unmasks its IRQ.
+pub fn init() {
+    set_frequency(DEFAULT_FREQUENCY);
+    pic::clear_mask(TIMER_IRQ);
+}
+
+/// Returns number of timer interrupts since [`init`], including those of
+/// the APIC timer once [`crate::apic::timer::init`] replaced the PIT.
+pub fn ticks() -> u64 {
+    // A tick between the two loads could carry into the high half.
+    crate::interrupts::without_interrupts(|| {
+        let low = TICKS_LOW.load(Ordering::Relaxed);
+        let high = TICKS_HIGH.load(Ordering::Relaxed);
+        (high as u64) << 32 | low as u64
+    })
+}
+
+/// Busy-waits until `n` more timer interrupts arrived.
+/// Interrupts must be enabled, otherwise this never returns.
+pub fn sleep_ticks(n: u64) {
+    let start = ticks();
+    while ticks() - start < n {
+        core::hint::spin_loop();
+    }
+}
+
+/// Advances [`ticks`] by one, called with interrupts disabled by the handler
+/// of whichever timer runs.
+pub(crate) fn tick() {
+    let low = TICKS_LOW.load(Ordering::Relaxed).wrapping_add(1);
+    TICKS_LOW.store(low, Ordering::Relaxed);
+    if low == 0 {
+        TICKS_HIGH.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) extern "C" fn timer_interrupt_handler(_frame: &InterruptStackFrame) {
+    crate::interrupts::count(pic::PIC1_OFFSET + TIMER_IRQ);
+    tick();
+    pic::send_eoi(TIMER_IRQ);
+}
+
+#[test_case]
+fn divisor_is_clamped() {
+    assert_eq!(divisor(100), 11931);
+    assert_eq!(divisor(1), u16::MAX);
+    assert_eq!(divisor(0), u16::MAX);
+    assert_eq!(divisor(BASE_FREQUENCY * 2), 1);
+}
+
+#[test_case]
+fn ticks_advance() {
+    set_frequency(1000);
+    let start = ticks();
+    sleep_ticks(10);
+    assert!(ticks() >= start + 10);
+    set_frequency(DEFAULT_FREQUENCY);
+}
+
+#[test_case]
+fn ticks_carry_into_high_half() {
+    crate::interrupts::without_interrupts(|| {
+        let low = TICKS_LOW.load(Ordering::Relaxed);
+        let high = TICKS_HIGH.load(Ordering::Relaxed);
+        TICKS_LOW.store(u32::MAX, Ordering::Relaxed);
+        tick();
+        assert_eq!(ticks(), (high as u64 + 1) << 32);
+        TICKS_LOW.store(low, Ordering::Relaxed);
+        TICKS_HIGH.store(high, Ordering::Relaxed);
+    });
+}
+
+#[test_case]
+fn beep_silences_speaker() {
+    beep(440, 1);
+    assert_eq!(unsafe { SPEAKER.read() } & SPEAKER_ENABLE, 0);
+}
@@ -1,38 +1,223 @@
+//! Typed I/O ports.
+//!
+//! Accessing a port can have arbitrary side effects on hardware, so reads and
+//! writes are `unsafe`. Drivers such as [`crate::pic`] or [`crate::serial`]
+//! wrap the ports of their device in safe functions.
 use core::arch::asm;
+use core::marker::PhantomData;
 
-pub struct Port {
+/// Value that can be read from an I/O port.
+pub trait PortRead {
+    /// Reads value from `port` with the `in` instruction of matching width.
+    unsafe fn read_from_port(port: u16) -> Self;
+}
+
+/// Value that can be written to an I/O port.
+pub trait PortWrite {
+    /// Writes `value` to `port` with the `out` instruction of matching width.
+    unsafe fn write_to_port(port: u16, value: Self);
+}
+
+impl PortRead for u8 {
+    #[inline]
+    unsafe fn read_from_port(port: u16) -> Self {
+        let value: u8;
+        asm!(
+            "in al, dx",
+            out("al") value,
+            in("dx") port,
+            options(nostack, preserves_flags, nomem),
+        );
+        value
+    }
+}
+
+impl PortRead for u16 {
+    #[inline]
+    unsafe fn read_from_port(port: u16) -> Self {
+        let value: u16;
+        asm!(
+            "in ax, dx",
+            out("ax") value,
+            in("dx") port,
+            options(nostack, preserves_flags, nomem),
+        );
+        value
+    }
+}
+
+impl PortRead for u32 {
+    #[inline]
+    unsafe fn read_from_port(port: u16) -> Self {
+        let value: u32;
+        asm!(
+            "in eax, dx",
+            out("eax") value,
+            in("dx") port,
+            options(nostack, preserves_flags, nomem),
+        );
+        value
+    }
+}
+
+impl PortWrite for u8 {
+    #[inline]
+    unsafe fn write_to_port(port: u16, value: Self) {
+        asm!(
+            "out dx, al",
+            in("dx") port,
+            in("al") value,
+            options(nostack, preserves_flags, nomem),
+        );
+    }
+}
+
+impl PortWrite for u16 {
+    #[inline]
+    unsafe fn write_to_port(port: u16, value: Self) {
+        asm!(
+            "out dx, ax",
+            in("dx") port,
+            in("ax") value,
+            options(nostack, preserves_flags, nomem),
+        );
+    }
+}
+
+impl PortWrite for u32 {
+    #[inline]
+    unsafe fn write_to_port(port: u16, value: Self) {
+        asm!(
+            "out dx, eax",
+            in("dx") port,
+            in("eax") value,
+            options(nostack, preserves_flags, nomem),
+        );
+    }
+}
+
+/// I/O port transferring values of type `T` (`u8`, `u16` or `u32`).
+pub struct Port<T> {
     port: u16,
+    phantom: PhantomData<T>,
 }
 
-impl Port {
-    pub fn new(port: u16) -> Self {
+impl<T> Port<T> {
+    pub const fn new(port: u16) -> Self {
         Self {
             port,
+            phantom: PhantomData,
         }
     }
+}
+
+impl<T: PortRead + PortWrite> Port<T> {
+    /// Reads value from the port.
+    ///
+    /// # Safety
+    /// Reading some ports changes state of the device behind them.
+    #[inline]
+    pub unsafe fn read(&self) -> T {
+        T::read_from_port(self.port)
+    }
 
+    /// Writes `value` to the port.
+    ///
+    /// # Safety
+    /// Writing a port can make the device behind it do anything,
+    /// including overwriting memory.
     #[inline]
-    pub fn write(&self, value: u32) {
-        unsafe {
-            asm!(
-                "out dx, eax",
-                in("dx") self.port,
-                in("eax") value,
-                options(nostack, preserves_flags, nomem),
-            );
+    pub unsafe fn write(&self, value: T) {
+        T::write_to_port(self.port, value)
+    }
+}
+
+/// I/O port that can only be read from.
+pub struct PortReadOnly<T> {
+    port: u16,
+    phantom: PhantomData<T>,
+}
+
+impl<T> PortReadOnly<T> {
+    pub const fn new(port: u16) -> Self {
+        Self {
+            port,
+            phantom: PhantomData,
         }
     }
+}
 
+impl<T: PortRead> PortReadOnly<T> {
+    /// Reads value from the port, see [`Port::read`].
     #[inline]
-    pub fn write_byte(&self, value: u8) {
-        unsafe {
-            asm!(
-                "out dx, al",
-                in("dx") self.port,
-                in("al") value,
-                options(nostack, preserves_flags, nomem),
-            );
+    pub unsafe fn read(&self) -> T {
+        T::read_from_port(self.port)
+    }
+}
+
+/// I/O port that can only be written to.
+pub struct PortWriteOnly<T> {
+    port: u16,
+    phantom: PhantomData<T>,
+}
+
+impl<T> PortWriteOnly<T> {
+    pub const fn new(port: u16) -> Self {
+        Self {
+            port,
+            phantom: PhantomData,
         }
     }
 }
 
+impl<T: PortWrite> PortWriteOnly<T> {
+    /// Writes `value` to the port, see [`Port::write`].
+    #[inline]
+    pub unsafe fn write(&self, value: T) {
+        T::write_to_port(self.port, value)
+    }
+}
+
+/// Waits a tiny moment (about 1 to 4 microseconds) by writing to the unused port `0x80`.
+///
+/// Slow legacy devices such as the PIC need this delay between consecutive commands.
+#[inline]
+pub fn io_wait() {
+    // Port 0x80 is only used by POST codes of the BIOS.
+    unsafe { PortWriteOnly::<u8>::new(0x80).write(0) };
+}
+
+#[test_case]
+fn io_wait_returns() {
+    io_wait();
+}
+
+#[test_case]
+fn port_u8_round_trip() {
+    // VGA CRT controller index register keeps the last written index.
+    let index = Port::<u8>::new(0x3d4);
+    unsafe {
+        index.write(0x0e);
+        assert_eq!(index.read(), 0x0e);
+    }
+}
+
+#[test_case]
+fn port_u32_round_trip() {
+    // PCI configuration address register: bus 0, device 0, function 0.
+    let address = Port::<u32>::new(0xcf8);
+    unsafe {
+        address.write(0x8000_0000);
+        assert_eq!(address.read(), 0x8000_0000);
+    }
+}
+
+#[test_case]
+fn port_u16_read() {
+    // Vendor id of the host bridge selected above, Intel under QEMU.
+    let data = Port::<u16>::new(0xcfc);
+    unsafe {
+        Port::<u32>::new(0xcf8).write(0x8000_0000);
+        assert_eq!(data.read(), 0x8086);
+    }
+}
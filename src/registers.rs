@@ -0,0 +1,52 @@
+//! Access to the control registers of the CPU. Model-specific registers
+//! are accessed through [`crate::cpu`].
+use core::arch::asm;
+
+/// Returns linear address whose access caused the last page fault (`CR2`).
+#[inline]
+pub fn read_cr2() -> usize {
+    let value: usize;
+    unsafe {
+    asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Returns physical address of the active page directory with its flags (`CR3`).
+#[inline]
+pub fn read_cr3() -> usize {
+    let value: usize;
+    unsafe {
+    asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Loads physical address of a page directory with its flags into `CR3`,
+/// which also flushes all non-global TLB entries.
+///
+/// # Safety
+/// With paging enabled the new page directory must map the running code.
+#[inline]
+pub unsafe fn write_cr3(value: usize) {
+    asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+/// Returns the system control flags (`CR0`).
+#[inline]
+pub fn read_cr0() -> usize {
+    let value: usize;
+    unsafe {
+    asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Sets the system control flags (`CR0`).
+///
+/// # Safety
+/// Changes fundamental behavior of the CPU, such as whether paging is enabled.
+#[inline]
+pub unsafe fn write_cr0(value: usize) {
+    asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+}
@@ -0,0 +1,129 @@
+//! Real-time clock (RTC) of the CMOS, the wall-clock time source.
+use crate::interrupts::without_interrupts;
+use crate::port::{Port, PortWriteOnly};
+
+const INDEX: PortWriteOnly<u8> = PortWriteOnly::new(0x70);
+const DATA: Port<u8> = Port::new(0x71);
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+/// Status register A bit: the RTC is updating its registers.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status register B bit: hours are in 24 hour format.
+const HOUR_24: u8 = 1 << 1;
+/// Status register B bit: values are binary instead of BCD.
+const BINARY: u8 = 1 << 2;
+/// Hours bit marking PM in 12 hour format.
+const PM: u8 = 1 << 7;
+
+/// Date and time as kept by the RTC, usually in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Converts two digit binary-coded decimal `value` to binary.
+const fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Reads CMOS register `register`. The NMI disable bit 7 of the index is left clear.
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        INDEX.write(register);
+        DATA.read()
+    }
+}
+
+/// Reads raw time registers, in the order of [`DateTime`] without the century.
+fn read_raw() -> [u8; 6] {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    [YEAR, MONTH, DAY, HOURS, MINUTES, SECONDS].map(read_register)
+}
+
+/// Converts raw time registers to [`DateTime`] according to status register B `status_b`.
+fn decode(raw: [u8; 6], status_b: u8) -> DateTime {
+    let [year, month, day, hours, minutes, seconds] = raw;
+    let pm = hours & PM != 0;
+    let convert = |value: u8| if status_b & BINARY != 0 { value } else { bcd_to_binary(value) };
+
+    let mut hour = convert(hours & !PM);
+    if status_b & HOUR_24 == 0 {
+        // 12 AM is midnight and 12 PM is noon.
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    DateTime {
+        // The century register isn't standardized, assume 20xx.
+        year: 2000 + convert(year) as u16,
+        month: convert(month),
+        day: convert(day),
+        hour,
+        minute: convert(minutes),
+        second: convert(seconds),
+    }
+}
+
+/// Returns current date and time.
+///
+/// The registers are read until two reads agree, so an update
+/// in the middle of reading can't produce an inconsistent time.
+#[allow(dead_code)]
+pub fn read_datetime() -> DateTime {
+    // An interrupt handler touching the CMOS would change the selected register.
+    without_interrupts(|| {
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        decode(raw, read_register(STATUS_B))
+    })
+}
+
+#[test_case]
+fn bcd_is_converted() {
+    assert_eq!(bcd_to_binary(0x00), 0);
+    assert_eq!(bcd_to_binary(0x09), 9);
+    assert_eq!(bcd_to_binary(0x10), 10);
+    assert_eq!(bcd_to_binary(0x59), 59);
+}
+
+#[test_case]
+fn raw_time_is_decoded() {
+    let bcd_12_hour = [0x24, 0x12, 0x31, PM | 0x11, 0x59, 0x30];
+    assert_eq!(decode(bcd_12_hour, 0), DateTime {
+        year: 2024, month: 12, day: 31, hour: 23, minute: 59, second: 30,
+    });
+    assert_eq!(decode([0x24, 0x01, 0x01, 0x12, 0, 0], 0).hour, 0);
+    assert_eq!(decode([24, 1, 1, 13, 5, 6], BINARY | HOUR_24).hour, 13);
+}
+
+#[test_case]
+fn datetime_is_plausible() {
+    let now = read_datetime();
+    assert!(now.year >= 2000);
+    assert!((1..=12).contains(&now.month));
+    assert!((1..=31).contains(&now.day));
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
@@ -0,0 +1,76 @@
+//! Boot-time self-test of core subsystems, run by debug builds.
+//! Every check prints a pass or fail line to serial.
+use crate::essentials::Mutex;
+use crate::interrupts::IDT;
+use crate::monitor::VGA::BUFFER;
+use crate::port::Port;
+use crate::serial_println;
+
+/// I/O port of the Bochs and QEMU debug console, which the runner enables with `-debugcon`.
+const DEBUGCON: u16 = 0xe9;
+/// Value read from [`DEBUGCON`] when the debug console is present.
+const DEBUGCON_READBACK: u8 = 0xe9;
+
+/// Name of a check and the check, which returns whether it passed.
+type Check = (&'static str, fn() -> bool);
+
+/// Checks run by [`run`].
+const CHECKS: [Check; 4] = [
+    ("vga round trip", vga_round_trip),
+    ("mutex try_lock", mutex_excludes),
+    ("port read/write", port_round_trip),
+    ("idt limit", idt_limit),
+];
+
+/// Runs all checks, returns whether all of them passed.
+/// The IDT must be loaded.
+pub fn run() -> bool {
+    let mut passed = true;
+    for (name, check) in CHECKS {
+        let ok = check();
+        serial_println!("selftest {}: {}", name, if ok { "pass" } else { "FAIL" });
+        passed &= ok;
+    }
+    passed
+}
+
+/// Inverts the top left cell of the display, reads it back and restores it.
+fn vga_round_trip() -> bool {
+    let mut monitor = BUFFER.lock_irqsafe();
+    let attribute = monitor.read_attr_at(0, 0);
+
+    monitor.invert_cell(0, 0);
+    // Inverting swaps the foreground and background nibbles.
+    let inverted = monitor.read_attr_at(0, 0) == attribute.rotate_left(4);
+    monitor.invert_cell(0, 0);
+    inverted && monitor.read_attr_at(0, 0) == attribute
+}
+
+/// Takes a lock with `try_lock` and checks that it can't be taken twice.
+fn mutex_excludes() -> bool {
+    let mutex = Mutex::new(0);
+    let guard = mutex.try_lock();
+    let excluded = guard.is_some() && mutex.try_lock().is_none();
+    drop(guard);
+    excluded && mutex.try_lock().is_some()
+}
+
+/// Writes a newline to the debug console and reads it back.
+fn port_round_trip() -> bool {
+    let port: Port<u8> = Port::new(DEBUGCON);
+    // The runner sends the debug console to `null`, the newline isn't shown anywhere.
+    unsafe {
+        port.write(b'\n');
+        port.read() == DEBUGCON_READBACK
+    }
+}
+
+/// Checks that `sidt` reports the limit of a table covering all 256 vectors.
+fn idt_limit() -> bool {
+    IDT::loaded_limit() == 256 * 8 - 1
+}
+
+#[test_case]
+fn checks_pass() {
+    assert!(run());
+}
@@ -0,0 +1,131 @@
+//! Driver for the 16550 UART, used for logging on COM1.
+use core::fmt;
+
+use crate::console::Console;
+use crate::essentials::Mutex;
+use crate::port::{Port, PortReadOnly};
+use lazy_static::lazy_static;
+
+/// Prints to COM1.
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+/// Prints to COM1, appending a newline.
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// I/O base of COM1.
+pub const COM1: u16 = 0x3f8;
+/// Baud rate set by [`SerialPort::init`].
+pub const BAUD_RATE: u32 = 38400;
+/// Frequency of the UART clock divided by 16, the highest baud rate.
+const MAX_BAUD_RATE: u32 = 115200;
+
+/// Line control: divisor latch access bit.
+const DLAB: u8 = 0x80;
+/// Line control: 8 data bits, no parity, 1 stop bit.
+const LINE_8N1: u8 = 0x03;
+/// FIFO control: enable and clear both FIFOs, 14 byte receive threshold.
+const FIFO_ENABLE: u8 = 0xc7;
+/// Modem control: data terminal ready and request to send.
+const MODEM_DTR_RTS: u8 = 0x03;
+/// Line status: transmitter holding register is empty.
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+lazy_static! {
+    /// Serial port on COM1, initialized on first use.
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let serial = unsafe { SerialPort::new(COM1) };
+        serial.init();
+        Mutex::new(serial)
+    };
+}
+
+/// Writes `args` to [`SERIAL1`] with interrupts masked, like the VGA `_print`.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    SERIAL1.lock_irqsafe().write_fmt(args).unwrap();
+}
+
+/// Serial port with registers at consecutive I/O ports.
+pub struct SerialPort {
+    /// Transmit/receive buffer; low byte of the divisor with DLAB set.
+    data: Port<u8>,
+    /// Interrupt enable; high byte of the divisor with DLAB set.
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: PortReadOnly<u8>,
+}
+
+impl SerialPort {
+    /// Creates serial port with registers starting at I/O port `base`.
+    ///
+    /// # Safety
+    /// `base` must be the base of a 16550 compatible UART not used elsewhere.
+    pub const unsafe fn new(base: u16) -> Self {
+        Self {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: PortReadOnly::new(base + 5),
+        }
+    }
+
+    /// Sets [`BAUD_RATE`], 8N1 and enables FIFOs. Interrupts of the UART are disabled.
+    pub fn init(&self) {
+        let divisor = (MAX_BAUD_RATE / BAUD_RATE) as u16;
+
+        // `new` guarantees the ports belong to a UART.
+        unsafe {
+            self.interrupt_enable.write(0);
+
+            self.line_control.write(DLAB);
+            self.data.write(divisor as u8);
+            self.interrupt_enable.write((divisor >> 8) as u8);
+            self.line_control.write(LINE_8N1);
+
+            self.fifo_control.write(FIFO_ENABLE);
+            self.modem_control.write(MODEM_DTR_RTS);
+        }
+    }
+
+    /// Sends `byte`, waiting until the transmitter is ready for it.
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.line_status.read() & TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            self.data.write(byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+impl Console for SerialPort {
+    fn write_byte(&mut self, byte: u8) {
+        SerialPort::write_byte(self, byte);
+    }
+}
+
+#[test_case]
+fn serial_println_returns() {
+    crate::serial_println!("serial println returns");
+}
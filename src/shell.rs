@@ -0,0 +1,193 @@
+//! Interactive prompt reading commands from the keyboard.
+use alloc::collections::VecDeque;
+use alloc::string::String;
+
+use crate::keyboard;
+use crate::monitor::VGA::BUFFER;
+use crate::println;
+
+/// Longest command line, longer input is cut off.
+const LINE_LENGTH: usize = 78;
+
+/// Most commands kept in the [`History`].
+#[cfg(not(test))]
+const HISTORY_LENGTH: usize = 16;
+
+/// Built-in commands with their descriptions, printed by `help`.
+const COMMANDS: [(&str, &str); 5] = [
+    ("clear", "clear the screen"),
+    ("echo <text>", "print <text>"),
+    ("help", "list commands"),
+    ("interrupts", "print how often each interrupt fired"),
+    ("ticks", "print timer ticks since boot"),
+];
+
+/// Reads and executes commands forever. Test builds run the tests instead.
+#[cfg(not(test))]
+pub fn run() -> ! {
+    let mut line = [0; LINE_LENGTH];
+    let mut history = History::new(HISTORY_LENGTH);
+    loop {
+        crate::print!("> ");
+        let len = keyboard::read_line_with(&mut line, |key, buf| {
+            let command = match key {
+                keyboard::UP => history.previous(),
+                _ => history.next(),
+            }?;
+            let len = core::cmp::min(command.len(), buf.len());
+            buf[..len].copy_from_slice(&command.as_bytes()[..len]);
+            Some(len)
+        });
+        history.push(&line[..len]);
+        execute(&line[..len]);
+    }
+}
+
+/// Previously entered commands, oldest first, browsed with the up and down arrows.
+///
+/// Browsing wraps around at both ends: before the oldest command comes the newest.
+struct History {
+    commands: VecDeque<String>,
+    capacity: usize,
+    /// Index of the command recalled last, `None` when not browsing.
+    position: Option<usize>,
+}
+
+impl History {
+    /// Creates empty history keeping the last `capacity` commands.
+    fn new(capacity: usize) -> Self {
+        Self { commands: VecDeque::with_capacity(capacity), capacity, position: None }
+    }
+
+    /// Adds `line` as the newest command, dropping the oldest one when full,
+    /// and stops browsing. Blank lines aren't kept.
+    fn push(&mut self, line: &[u8]) {
+        self.position = None;
+        if trim(line).is_empty() || self.capacity == 0 {
+            return;
+        }
+        if self.commands.len() == self.capacity {
+            self.commands.pop_front();
+        }
+        // `read_line` only stores ASCII.
+        self.commands.push_back(String::from(core::str::from_utf8(line).unwrap_or("")));
+    }
+
+    /// Returns the command before the one recalled last, or the newest one.
+    fn previous(&mut self) -> Option<&str> {
+        let last = self.commands.len().checked_sub(1)?;
+        let position = match self.position {
+            Some(0) | None => last,
+            Some(position) => position - 1,
+        };
+        self.position = Some(position);
+        Some(&self.commands[position])
+    }
+
+    /// Returns the command after the one recalled last, or the oldest one.
+    fn next(&mut self) -> Option<&str> {
+        let last = self.commands.len().checked_sub(1)?;
+        let position = match self.position {
+            Some(position) if position < last => position + 1,
+            _ => 0,
+        };
+        self.position = Some(position);
+        Some(&self.commands[position])
+    }
+}
+
+/// Splits `line` into the command and its arguments at the first space,
+/// ignoring surrounding spaces.
+fn parse(line: &[u8]) -> (&[u8], &[u8]) {
+    let line = trim(line);
+    match line.iter().position(|&byte| byte == b' ') {
+        Some(space) => (&line[..space], trim(&line[space + 1..])),
+        None => (line, &[]),
+    }
+}
+
+/// Returns `bytes` without leading and trailing spaces.
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&byte| byte != b' ').unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|&byte| byte != b' ').map_or(start, |last| last + 1);
+    &bytes[start..end]
+}
+
+/// Executes command `line`. Empty lines do nothing.
+fn execute(line: &[u8]) {
+    let (command, args) = parse(line);
+    // `read_line` only stores ASCII.
+    let text = |bytes| core::str::from_utf8(bytes).unwrap_or("");
+
+    match command {
+        b"" => {},
+        b"clear" => BUFFER.lock_irqsafe().clear(),
+        b"echo" => println!("{}", text(args)),
+        b"help" => {
+            for (usage, description) in COMMANDS.iter() {
+                println!("{:12} {}", usage, description);
+            }
+        },
+        b"interrupts" => crate::interrupts::print_stats(),
+        b"ticks" => println!("{}", crate::pit::ticks()),
+        _ => println!("unknown command: {}, try help", text(command)),
+    }
+}
+
+#[test_case]
+fn commands_are_parsed() {
+    assert_eq!(parse(b"  echo  hello world "), (&b"echo"[..], &b"hello world"[..]));
+    assert_eq!(parse(b"ticks"), (&b"ticks"[..], &b""[..]));
+    assert_eq!(parse(b"   "), (&b""[..], &b""[..]));
+}
+
+#[test_case]
+fn history_is_browsed() {
+    let mut history = History::new(3);
+    assert_eq!(history.previous(), None);
+    assert_eq!(history.next(), None);
+
+    for command in ["zero", "one", " ", "two", "three"] {
+        history.push(command.as_bytes());
+    }
+    // "zero" was dropped and the blank line not kept.
+    assert_eq!(history.previous(), Some("three"));
+    assert_eq!(history.previous(), Some("two"));
+    assert_eq!(history.previous(), Some("one"));
+    assert_eq!(history.previous(), Some("three"));
+    assert_eq!(history.next(), Some("one"));
+    assert_eq!(history.next(), Some("two"));
+
+    // Entering a command starts over at the newest one.
+    history.push(b"four");
+    assert_eq!(history.previous(), Some("four"));
+    history.push(b"");
+    assert_eq!(history.next(), Some("two"));
+}
+
+#[test_case]
+fn clear_command_clears_screen() {
+    // c, l, e, a, r and Enter, each pressed and released.
+    let scancodes = [0x2e, 0x26, 0x12, 0x1e, 0x13, 0x1c];
+    crate::interrupts::without_interrupts(|| {
+        for &scancode in scancodes.iter() {
+            keyboard::handle_scancode(scancode);
+            keyboard::handle_scancode(scancode | 0x80);
+        }
+    });
+    // Don't leave the scancodes to other readers.
+    while keyboard::read_scancode().is_some() {}
+
+    let mut line = [0; LINE_LENGTH];
+    let len = keyboard::read_line(&mut line);
+    assert_eq!(&line[..len], b"clear");
+    execute(&line[..len]);
+
+    let monitor = BUFFER.lock_irqsafe();
+    assert_eq!(monitor.cursor_position(), (0, 0));
+    for y in 0..25 {
+        for x in 0..80 {
+            assert_eq!(monitor.read_char_at(x, y), b' ');
+        }
+    }
+}
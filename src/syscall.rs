@@ -0,0 +1,136 @@
+//! System calls through `int 0x80`, the entry point for userland.
+//!
+//! The number of the call is passed in `eax` and its argument in `ecx`. The result
+//! is returned in `eax`, all other registers are preserved.
+use core::arch::{asm, global_asm};
+
+use crate::interrupts::HandlerFunc;
+
+/// Interrupt vector of system calls, its gate has DPL 3.
+pub const VECTOR: u8 = 0x80;
+
+/// Prints the NUL-terminated UTF-8 string at address `arg`, returns its length in bytes.
+// The call numbers are for callers of `int 0x80`, which only the tests are so far.
+#[allow(dead_code)]
+pub const WRITE: usize = 0;
+/// Ends the calling program. There are no programs yet, so it halts the kernel.
+#[allow(dead_code)]
+pub const EXIT: usize = 1;
+/// Result of an unknown or failed system call.
+pub const ERROR: usize = usize::MAX;
+
+/// Handlers of the system calls, indexed by number.
+const TABLE: [fn(usize) -> usize; 2] = [write, exit];
+
+/// General purpose registers in the order `pushad` stores them, lowest address first.
+#[derive(Debug)]
+#[repr(C)]
+pub struct SavedRegisters {
+    pub edi: usize,
+    pub esi: usize,
+    pub ebp: usize,
+    /// Stack pointer before `pushad`.
+    pub esp: usize,
+    pub ebx: usize,
+    pub edx: usize,
+    pub ecx: usize,
+    pub eax: usize,
+}
+
+global_asm!(
+    ".global syscall_trampoline",
+    "syscall_trampoline:",
+    "pushad",
+    // `push esp` pushes the value from before the push, the saved registers.
+    "push esp",
+    "cld",
+    "call {dispatch}",
+    "add esp, 4",
+    // Restores the caller's registers, `eax` now holding the result.
+    "popad",
+    "iretd",
+    dispatch = sym dispatch,
+);
+
+extern "C" {
+    fn syscall_trampoline();
+}
+
+/// Returns entry point of the [`VECTOR`] gate.
+pub(crate) fn entry() -> HandlerFunc {
+    HandlerFunc(syscall_trampoline)
+}
+
+/// Runs the system call `registers` of the caller describe and replaces their `eax`
+/// by its result.
+extern "C" fn dispatch(registers: &mut SavedRegisters) {
+    crate::interrupts::count(VECTOR);
+    registers.eax = TABLE.get(registers.eax).map_or(ERROR, |call| call(registers.ecx));
+}
+
+/// [`WRITE`]. The string isn't validated beyond UTF-8, callers are trusted
+/// as long as there is no userland.
+fn write(address: usize) -> usize {
+    let start = address as *const u8;
+    let mut len = 0;
+    while unsafe { *start.add(len) } != 0 {
+        len += 1;
+    }
+
+    match core::str::from_utf8(unsafe { core::slice::from_raw_parts(start, len) }) {
+        Ok(string) => {
+            crate::print!("{}", string);
+            len
+        },
+        Err(_) => ERROR,
+    }
+}
+
+/// [`EXIT`].
+fn exit(_code: usize) -> usize {
+    crate::info!("exit system call, halting");
+    crate::interrupts::halt_loop()
+}
+
+/// Issues system call `num` with argument `arg` and returns its result.
+#[allow(dead_code)]
+pub fn syscall(num: usize, arg: usize) -> usize {
+    let result;
+    unsafe {
+    asm!("int 0x80", inlateout("eax") num => result, in("ecx") arg);
+    }
+    result
+}
+
+#[test_case]
+fn dispatch_runs_table() {
+    let mut registers = SavedRegisters { edi: 0, esi: 0, ebp: 0, esp: 0, ebx: 0, edx: 0, ecx: 0, eax: WRITE };
+    registers.ecx = c"written by dispatch\n".as_ptr() as usize;
+    dispatch(&mut registers);
+    assert_eq!(registers.eax, 20);
+
+    registers.eax = WRITE;
+    registers.ecx = c"\xff".as_ptr() as usize;
+    dispatch(&mut registers);
+    assert_eq!(registers.eax, ERROR);
+
+    registers.eax = TABLE.len();
+    dispatch(&mut registers);
+    assert_eq!(registers.eax, ERROR);
+}
+
+#[test_case]
+fn write_prints_string() {
+    assert_eq!(syscall(WRITE, c"written by int 0x80\n".as_ptr() as usize), 20);
+    assert_eq!(syscall(WRITE, c"\xff".as_ptr() as usize), ERROR);
+    assert_eq!(syscall(TABLE.len(), 0), ERROR);
+}
+
+#[test_case]
+fn gate_allows_ring_3() {
+    use bit_field::BitField;
+
+    let attribute = crate::interrupts::IDT::modify(|idt| idt.type_attribute(VECTOR));
+    assert!(attribute.value().get_bit(7));
+    assert_eq!(attribute.value().get_bits(5..=6), 0b11);
+}
@@ -1,5 +1,122 @@
+//! Support for running tests in QEMU.
+//!
+//! Test output goes to the serial port, so it can be captured with `-serial stdio`.
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use crate::port::Port;
 
+/// Prints test output to the serial port and, with [`ECHO_TO_VGA`], to the VGA display.
+#[macro_export]
+macro_rules! test_print {
+    ($($arg:tt)*) => ($crate::test::_print(format_args!($($arg)*)));
+}
+
+/// Like [`test_print!`], but appends a newline.
+#[macro_export]
+macro_rules! test_println {
+    () => ($crate::test_print!("\n"));
+    ($($arg:tt)*) => ($crate::test_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Whether test output is also printed to the VGA display.
+///
+/// While panicking the display is skipped if it is locked: a failing assert
+/// in a test holding `BUFFER` would otherwise deadlock the panic handler.
+pub const ECHO_TO_VGA: bool = true;
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    crate::serial::_print(args);
+    if ECHO_TO_VGA {
+        if crate::panic::is_panicking() {
+            crate::monitor::VGA::_try_print(args);
+        } else {
+            crate::monitor::VGA::_print(args);
+        }
+    }
+}
+
+/// Set while a [`ShouldPanic`] test runs, the panic handler then passes the test.
+static EXPECTING_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Test run by `test_runner`.
+pub trait Testable {
+    /// Runs the test, printing its name and `[ok]` once it passes.
+    /// A failing test panics, which prints `[failed]` instead.
+    fn run(&self);
+
+    /// Returns name of the test.
+    fn name(&self) -> &'static str;
+
+    /// Whether the test passes by panicking, see [`ShouldPanic`].
+    fn should_panic(&self) -> bool {
+        false
+    }
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        crate::test_print!("{}... ", self.name());
+        self();
+        crate::test_println!("[ok]");
+    }
+
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// Test that passes only if `test` panics, a replacement for `#[should_panic]`:
+/// ```
+/// #[test_case]
+/// static DOUBLE_TAKE_PANICS: ShouldPanic = ShouldPanic {
+///     name: concat!(module_path!(), "::double_take_panics"),
+///     test: double_take_panics,
+/// };
+/// ```
+///
+/// A panic can't be recovered from, so the panic handler exits QEMU and
+/// the test ends the whole run. `test_runner` therefore runs it after all other
+/// tests, and only one such test per test binary is ever run: the first one whose
+/// name contains the `should_panic=<name>` kernel command line option,
+/// or the first one without the option.
+pub struct ShouldPanic {
+    /// Name printed for the test.
+    pub name: &'static str,
+    pub test: fn(),
+}
+
+impl Testable for ShouldPanic {
+    fn run(&self) {
+        crate::test_print!("{}... ", self.name);
+        EXPECTING_PANIC.store(true, Ordering::SeqCst);
+        (self.test)();
+        EXPECTING_PANIC.store(false, Ordering::SeqCst);
+
+        crate::test_println!("[failed]");
+        crate::test_println!("expected a panic");
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn should_panic(&self) -> bool {
+        true
+    }
+}
+
+/// Returns whether the running test is a [`ShouldPanic`] one.
+pub fn is_expecting_panic() -> bool {
+    EXPECTING_PANIC.load(Ordering::SeqCst)
+}
+
+/// Exit code written to the `isa-debug-exit` device.
+///
+/// QEMU exits with status `(code << 1) | 1`, so `Success` becomes 33 and `Failed` 35.
+/// Neither collides with QEMU's own failure status 1.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -7,7 +124,12 @@ pub enum QemuExitCode {
     Failed = 0x11,
 }
 
+/// Exits QEMU with `exit_code` through the `isa-debug-exit` device on port `0xf4`.
+///
+/// Does nothing when QEMU wasn't started with
+/// `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
 pub fn exit_qemu(exit_code: QemuExitCode) {
-    let port = Port::new(0xf4);
-    port.write(exit_code as u32);
+    let port = Port::<u32>::new(0xf4);
+    // Other than exiting, the debug exit device has no effect.
+    unsafe { port.write(exit_code as u32) };
 }